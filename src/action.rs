@@ -0,0 +1,54 @@
+use serde::Serialize;
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+#[serde(tag = "type")]
+pub enum Action {
+    Message(MessageAction),
+    Postback(PostbackAction),
+    Uri(UriAction),
+    #[serde(rename = "datetimepicker")]
+    DatetimePicker(DatetimePickerAction),
+}
+
+#[derive(Serialize, Debug)]
+pub struct MessageAction {
+    pub label: String,
+    pub text: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PostbackAction {
+    pub label: String,
+    pub data: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_text: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct UriAction {
+    pub label: String,
+    pub uri: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DatetimePickerAction {
+    pub label: String,
+    pub data: String,
+    pub mode: DatetimePickerMode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub initial: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum DatetimePickerMode {
+    Date,
+    Time,
+    Datetime,
+}
@@ -1,113 +1,1402 @@
-use crate::channel::Channel;
-use crate::oauth::OAuthError;
-use crate::reply::{respond, ReplyError};
+use crate::audience::AudienceGroupPage;
+use crate::bot::{BotClient, BotError, BotResult};
+use crate::channel::{invoke_handler, Channel, HandleWebhookEvent, LineEvent};
+use crate::config::MessagingApiConfig;
+use crate::event::{WebhookEvent, WebhookEventBatch};
+use crate::insight::{AggregateMessageStats, InsightFollowers, InsightMessageDelivery};
+use crate::member::MemberIdsPage;
+use crate::message::{MessageQuota, MessageQuotaConsumption};
+use crate::oauth::{OAuthError, VerifyTokenResponse};
+use crate::pool::{lock_handler, ChannelEntry, ChannelPool};
+use crate::reply::{respond, Reply, ReplyError, ReplyMessage};
 use crate::request::{RequestBody, RequestBodyError};
-use failure::Fail;
-use log::debug;
+use crate::rich_menu::{RichMenu, RichMenuSummary};
+use crate::user::UserProfile;
+use crate::webhook::{WebhookEndpointInfo, WebhookTestResult};
+use bytes::Bytes;
+use log::{debug, error, warn};
 use signature::Algorithm;
+use static_assertions::assert_impl_all;
+use std::any::Any;
 use std::collections::HashMap;
 use std::convert::TryFrom;
-use std::sync::Mutex;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use thiserror::Error;
+use uuid::Uuid;
 
+/// LINE Messaging APIのエントリポイント。
+///
+/// `MessagingApi`は`Send + Sync`であり、`Arc`で包んでスレッドや非同期タスク間で共有できる。
+/// ハンドラはチャンネル単位の`Mutex`で保護されるため、同一チャンネル宛てのイベント処理は直列化される。
+/// アクセストークンはハンドラとは別にロックするため、ハンドラ内から同一チャンネルのAPIを呼び出せる。
+/// 非同期コードから使用する場合は`tokio`フィーチャを有効にし、`*_async`メソッドを使用すること。
+/// 同期メソッドをシングルスレッドランタイム上で呼び出し、チャンネルが処理中の場合は`ChannelBusy`エラーとなる。
 pub struct MessagingApi {
-    channels: HashMap<String, Mutex<Channel>>,
+    pool: Arc<ChannelPool>,
+    router: Option<EventRouter>,
 }
 
+// イベントの内容から処理するチャンネルのユーザーIDを決定する関数。
+// 関数が保持するマップ等から引いたIDを返せるよう、所有したStringを返す。
+type EventRouter = Box<dyn Fn(&WebhookEvent) -> Option<String> + Send + Sync + 'static>;
+
+assert_impl_all!(MessagingApi: Send, Sync);
+
 impl MessagingApi {
     pub fn new() -> Self {
-        MessagingApi {
-            channels: HashMap::new(),
-        }
+        MessagingApi::with_pool(Arc::new(ChannelPool::new()))
+    }
+
+    pub fn with_config(config: MessagingApiConfig) -> Self {
+        MessagingApi::with_pool(Arc::new(ChannelPool::with_config(config)))
+    }
+
+    pub fn with_pool(pool: Arc<ChannelPool>) -> Self {
+        MessagingApi { pool, router: None }
     }
 
-    pub fn add_channel(mut self, channel: Channel) -> Self {
-        self.channels
-            .insert(channel.user_id.clone(), Mutex::new(channel));
+    pub fn with_router(
+        mut self,
+        router: impl Fn(&WebhookEvent) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.router = Some(Box::new(router));
         self
     }
 
-    fn get_channel(&self, user_id: &str) -> MessagingResult<&Mutex<Channel>> {
-        self.channels.get(user_id).ok_or(
-            MessagingError::Destination {
-                    message: "宛先ユーザーIDに該当するチャンネルが存在しません。".to_owned(),
-            }
-        )
+    pub fn add_channel(self, channel: Channel) -> Self {
+        self.pool.add_channel(channel);
+        self
+    }
+
+    // 処理中のイベントが完了するのを待ってから、登録済みのチャンネルを差し替える。
+    pub fn replace_channel(&self, channel: Channel) -> MessagingResult<()> {
+        let entry = self.get_channel(channel.user_id.as_str())?;
+        debug!("チャンネルを差し替えます。ユーザーID[{}]", channel.user_id);
+        let _handler = lock_handler(&entry.handler)?;
+        self.pool.add_channel(channel);
+        Ok(())
+    }
+
+    // ハンドラのロックを取らないため、ハンドラの呼び出し中にも更新できる。
+    pub fn update_secret(&self, channel_user_id: &str, new_secret: String) -> MessagingResult<()> {
+        let entry = self.get_channel(channel_user_id)?;
+        debug!(
+            "チャンネルシークレットを更新します。ユーザーID[{}]",
+            channel_user_id
+        );
+        entry.update_secret(new_secret);
+        Ok(())
+    }
+
+    // 処理中のイベントが完了するのを待ってから、ハンドラを差し替える。
+    pub fn update_handler(
+        &self,
+        channel_user_id: &str,
+        handler: impl HandleWebhookEvent + Send + 'static,
+    ) -> MessagingResult<()> {
+        let entry = self.get_channel(channel_user_id)?;
+        debug!(
+            "webhookハンドラを差し替えます。ユーザーID[{}]",
+            channel_user_id
+        );
+        *lock_handler(&entry.handler)? = Box::new(handler);
+        Ok(())
+    }
+
+    // 有効期限が不明なトークンは有効とみなす。
+    pub fn is_token_expired(&self, channel_user_id: &str) -> MessagingResult<bool> {
+        let entry = self.get_channel(channel_user_id)?;
+        let expired = entry.token.lock().unwrap().is_expired();
+        Ok(expired)
+    }
+
+    pub fn has_channel(&self, user_id: &str) -> bool {
+        self.pool.contains(user_id)
     }
 
-    pub fn sign(&self, message: String, digest: &[u8]) -> MessagingResult<RequestBody> {
-        let body = RequestBody::try_from(message)?;
-        let user_id = &body.destination;
+    pub fn pool(&self) -> &Arc<ChannelPool> {
+        &self.pool
+    }
+
+    // サービス終了時に呼び出し、チャンネル毎の発行上限(30件)を消費しないようトークンを失効させる。
+    // 失効させるのはライブラリが発行したトークン（有効期限を保持しているもの）のみとし、
+    // Channel::newで渡された長期のアクセストークン等、呼び出し側が管理するトークンには触れない。
+    pub fn shutdown(&self) -> Vec<MessagingResult<()>> {
+        debug!("ライブラリが発行したアクセストークンを失効します。");
+        self.pool
+            .channels()
+            .iter()
+            .filter_map(|entry| {
+                let mut token = entry.token.lock().unwrap();
+                if token.expires_at.is_none() {
+                    return None;
+                }
+                let access_token = token.value.take()?;
+                token.expires_at = None;
+                let result = crate::oauth::revoke_access_token(
+                    &entry.client,
+                    &entry.config.base_url,
+                    &access_token,
+                );
+                if let Err(e) = &result {
+                    error!(
+                        "アクセストークンの失効に失敗しました。ユーザーID[{}], エラー[{:?}]",
+                        entry.user_id, e
+                    );
+                }
+                Some(result.map_err(MessagingError::from))
+            })
+            .collect()
+    }
+
+    // 最初のリクエストより前にトークンを発行しておく場合に使用する。
+    // チャンネルに個別設定が無い場合は、プールの既定の設定とクライアントで発行する。
+    pub fn ensure_token(&self, channel_user_id: &str) -> MessagingResult<()> {
+        let entry = self.get_channel(channel_user_id)?;
+        with_retry(entry.config.max_retries, || entry.access_token())?;
+        Ok(())
+    }
+
+    fn get_channel(&self, user_id: &str) -> MessagingResult<Arc<ChannelEntry>> {
+        self.pool.get(user_id).ok_or(MessagingError::Destination {
+            message: "宛先ユーザーIDに該当するチャンネルが存在しません。".to_owned(),
+        })
+    }
+
+    pub fn sign(&self, message: String, digest: &[u8]) -> MessagingResult<WebhookEventBatch> {
+        let body = self.parse_body(message)?;
         debug!("webhookリクエストの署名検証を行います。");
-        let channel = self.get_channel(user_id)?.lock().unwrap();
+        let entry = self.get_channel(&body.destination)?;
+        let secret = entry.secret.read().unwrap();
+        Self::verify_signature(&secret, body, digest)
+    }
+
+    fn parse_body(&self, message: String) -> MessagingResult<RequestBody> {
+        if self.pool.config().reject_empty_events {
+            Ok(RequestBody::try_from_strict(message)?)
+        } else {
+            Ok(RequestBody::try_from(message)?)
+        }
+    }
+
+    fn verify_signature(
+        secret: &str,
+        body: RequestBody,
+        digest: &[u8],
+    ) -> MessagingResult<WebhookEventBatch> {
         // HMAC-SHA256-BASE64アルゴリズムに基づいて署名検査を行う。
-        let algorithm = Algorithm::HmacSha256Base64(&channel.secret);
+        let algorithm = Algorithm::HmacSha256Base64(secret);
         if !algorithm.verify(&body.src, digest) {
-            return Err(MessagingError::Signature{message: 
-                "webhookリクエストの署名検証の結果、リクエスト元の正当性を確認できませんでした。"
+            return Err(MessagingError::Signature {
+                message: "webhookリクエストの署名検証の結果、リクエスト元の正当性を確認できませんでした。"
                     .to_owned(),
-            })
+                channel_user_id: body.destination,
+                digest_hex: digest.iter().map(|b| format!("{:02x}", b)).collect(),
+            });
         }
         debug!("webhookリクエストの署名検証に成功しました。");
-        Ok(body)
+        Ok(WebhookEventBatch::from(body))
+    }
+
+    pub fn handle_event(
+        &self,
+        batch: WebhookEventBatch,
+    ) -> MessagingResult<Vec<MessagingResult<()>>> {
+        let results = self.handle_event_reporting(batch)?;
+        Ok(results.into_iter().map(|(_, result)| result).collect())
     }
 
-    pub fn handle_event(&self, body: RequestBody) -> MessagingResult<()> {
-        let user_id = &body.destination;
+    // 監視等でどのイベントの処理に失敗したかを特定できるよう、イベントと結果を対にして返す。
+    pub fn handle_event_reporting(
+        &self,
+        batch: WebhookEventBatch,
+    ) -> MessagingResult<Vec<(WebhookEvent, MessagingResult<()>)>> {
         debug!("webhookイベントのハンドリングを行います。");
-        let mut channel = self.get_channel(user_id)?.lock().unwrap();
-        for event in body.events {
-            if let Some(reply) = channel.handle_event(event) {
-                let token = Self::get_access_token(&mut channel)?;
-                respond(token, &reply)?;
+        // ルーティングしない場合は、宛先のチャンネルが存在しなければバッチ全体をエラーとする。
+        if self.router.is_none() {
+            self.get_channel(&batch.destination)?;
+        }
+        let mut results = Vec::with_capacity(batch.events.len());
+        for event in batch.events {
+            // 1イベントの失敗で後続イベントの処理を中断しないよう、結果をイベント毎に収集する。
+            let user_id = self.route(&event, &batch.destination);
+            let result = self
+                .get_channel(&user_id)
+                .and_then(|entry| Self::handle_single_event(&entry, &batch.destination, &event));
+            if let Err(e) = &result {
+                error!(
+                    "webhookイベントのハンドリングに失敗しました。ユーザーID[{}], エラー[{:?}]",
+                    user_id, e
+                );
             }
+            results.push((event, result));
+        }
+        Ok(results)
+    }
+
+    // ルーティング関数が無い、またはNoneを返したイベントは、リクエストの宛先のチャンネルで処理する。
+    fn route(&self, event: &WebhookEvent, destination: &str) -> String {
+        self.router
+            .as_ref()
+            .and_then(|router| router(event))
+            .unwrap_or_else(|| destination.to_owned())
+    }
+
+    pub fn sign_and_handle(
+        &self,
+        body: String,
+        signature: &[u8],
+    ) -> MessagingResult<Vec<MessagingResult<()>>> {
+        let batch = self.sign(body, signature)?;
+        self.handle_event(batch)
+    }
+
+    // ハンドラのロックはハンドラの呼び出し中のみ保持し、リプライの送信と再試行の待機中は解放する。
+    fn handle_single_event(
+        entry: &ChannelEntry,
+        destination: &str,
+        event: &WebhookEvent,
+    ) -> MessagingResult<()> {
+        let reply = {
+            let mut handler = lock_handler(&entry.handler)?;
+            call_handler(&mut **handler, entry, destination, event)?
+        };
+        if let Some(reply) = reply {
+            with_retry(entry.config.max_retries, || {
+                let token = entry.access_token()?;
+                Ok(respond(
+                    &entry.client,
+                    &entry.config.base_url,
+                    &token,
+                    &reply,
+                )?)
+            })?;
         }
         Ok(())
     }
 
-    fn get_access_token(channel: &mut Channel) -> MessagingResult<&str> {
-        match &channel.access_token {
-            Some(token) => debug!("既存のアクセストークンを使用します。トークン[{}]", token),
-            None => {
-                // アクセストークンが無いため新規に発番する。
-                let token = crate::oauth::issue_access_token(channel.id, &channel.secret)?;
-                channel.access_token = Some(token);
+    // HandleWebhookEventを実装せず、取り出したリプライトークンで直接リプライする場合に使用する。
+    pub fn respond_to(
+        &self,
+        channel_user_id: &str,
+        reply_token: &str,
+        messages: Vec<ReplyMessage>,
+        notification_disabled: bool,
+    ) -> MessagingResult<()> {
+        let reply = Reply::new_with_token(reply_token, messages, notification_disabled);
+        let entry = self.get_channel(channel_user_id)?;
+        with_retry(entry.config.max_retries, || {
+            let token = entry.access_token()?;
+            Ok(respond(
+                &entry.client,
+                &entry.config.base_url,
+                &token,
+                &reply,
+            )?)
+        })
+    }
+
+    pub fn get_message_content_preview(
+        &self,
+        channel_user_id: &str,
+        message_id: &str,
+    ) -> MessagingResult<Bytes> {
+        self.with_bot(channel_user_id, |bot| {
+            crate::message::get_content_preview(bot, message_id)
+        })
+    }
+
+    pub fn test_webhook(&self, channel_user_id: &str) -> MessagingResult<WebhookTestResult> {
+        self.with_bot(channel_user_id, crate::webhook::test_webhook)
+    }
+
+    pub fn get_webhook_endpoint(
+        &self,
+        channel_user_id: &str,
+    ) -> MessagingResult<WebhookEndpointInfo> {
+        self.with_bot(channel_user_id, crate::webhook::get_endpoint)
+    }
+
+    pub fn list_audience_groups(
+        &self,
+        channel_user_id: &str,
+        page: u32,
+        description: Option<&str>,
+    ) -> MessagingResult<AudienceGroupPage> {
+        self.with_bot(channel_user_id, |bot| {
+            crate::audience::list_audience_groups(bot, page, description)
+        })
+    }
+
+    pub fn get_insight_message_delivery(
+        &self,
+        channel_user_id: &str,
+        date: &str,
+    ) -> MessagingResult<InsightMessageDelivery> {
+        self.with_bot(channel_user_id, |bot| {
+            crate::insight::get_message_delivery(bot, date)
+        })
+    }
+
+    pub fn get_insight_followers(
+        &self,
+        channel_user_id: &str,
+        date: &str,
+    ) -> MessagingResult<InsightFollowers> {
+        self.with_bot(channel_user_id, |bot| {
+            crate::insight::get_followers(bot, date)
+        })
+    }
+
+    pub fn get_profile(
+        &self,
+        channel_user_id: &str,
+        user_id: &str,
+    ) -> MessagingResult<UserProfile> {
+        self.with_bot(channel_user_id, |bot| {
+            crate::user::get_profile(bot, user_id)
+        })
+    }
+
+    // 友だちでないユーザー等で一部の取得に失敗しても他の結果を失わないよう、ユーザー毎の結果を返す。
+    pub fn batch_get_profiles(
+        &self,
+        channel_user_id: &str,
+        user_ids: &[&str],
+    ) -> MessagingResult<Vec<(String, MessagingResult<UserProfile>)>> {
+        let entry = self.get_channel(channel_user_id)?;
+        let config = &entry.config;
+        let token = with_retry(config.max_retries, || entry.access_token())?;
+        let bot = BotClient::new(&entry.client, config, &token);
+        let concurrency = self.pool.config().profile_concurrency.max(1);
+        let mut profiles = Vec::with_capacity(user_ids.len());
+        for chunk in user_ids.chunks(concurrency) {
+            let results: Vec<MessagingResult<UserProfile>> = thread::scope(|scope| {
+                let handles: Vec<_> = chunk
+                    .iter()
+                    .map(|user_id| {
+                        let bot = &bot;
+                        let max_retries = config.max_retries;
+                        scope.spawn(move || {
+                            with_retry(max_retries, || Ok(crate::user::get_profile(bot, user_id)?))
+                        })
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().unwrap())
+                    .collect()
+            });
+            for (user_id, result) in chunk.iter().zip(results) {
+                if let Err(e) = &result {
+                    error!(
+                        "プロフィールの取得に失敗しました。ユーザーID[{}], エラー[{:?}]",
+                        user_id, e
+                    );
+                }
+                profiles.push(((*user_id).to_owned(), result));
             }
         }
-        let token = channel.access_token.as_ref().unwrap();
-        Ok(token)
+        Ok(profiles)
+    }
+
+    pub fn get_group_member_ids(
+        &self,
+        channel_user_id: &str,
+        group_id: &str,
+        continuation_token: Option<&str>,
+    ) -> MessagingResult<MemberIdsPage> {
+        self.with_bot(channel_user_id, |bot| {
+            crate::member::get_group_member_ids(bot, group_id, continuation_token)
+        })
+    }
+
+    pub fn get_room_member_ids(
+        &self,
+        channel_user_id: &str,
+        room_id: &str,
+        continuation_token: Option<&str>,
+    ) -> MessagingResult<MemberIdsPage> {
+        self.with_bot(channel_user_id, |bot| {
+            crate::member::get_room_member_ids(bot, room_id, continuation_token)
+        })
+    }
+
+    pub fn issue_link_token(
+        &self,
+        channel_user_id: &str,
+        user_id: &str,
+    ) -> MessagingResult<String> {
+        self.with_bot_once(channel_user_id, |bot| {
+            crate::user::issue_link_token(bot, user_id)
+        })
+    }
+
+    pub fn get_rich_menu(
+        &self,
+        channel_user_id: &str,
+        rich_menu_id: &str,
+    ) -> MessagingResult<RichMenu> {
+        self.with_bot(channel_user_id, |bot| {
+            crate::rich_menu::get(bot, rich_menu_id)
+        })
+    }
+
+    pub fn delete_rich_menu(
+        &self,
+        channel_user_id: &str,
+        rich_menu_id: &str,
+    ) -> MessagingResult<()> {
+        self.with_bot(channel_user_id, |bot| {
+            crate::rich_menu::delete(bot, rich_menu_id)
+        })
+    }
+
+    pub fn set_default_rich_menu(
+        &self,
+        channel_user_id: &str,
+        rich_menu_id: &str,
+    ) -> MessagingResult<()> {
+        self.with_bot(channel_user_id, |bot| {
+            crate::rich_menu::set_default(bot, rich_menu_id)
+        })
+    }
+
+    pub fn cancel_default_rich_menu(&self, channel_user_id: &str) -> MessagingResult<()> {
+        self.with_bot(channel_user_id, crate::rich_menu::cancel_default)
+    }
+
+    pub fn get_rich_menu_list(
+        &self,
+        channel_user_id: &str,
+    ) -> MessagingResult<Vec<RichMenuSummary>> {
+        self.with_bot(channel_user_id, crate::rich_menu::get_list)
+    }
+
+    pub fn copy_rich_menu(
+        &self,
+        channel_user_id: &str,
+        rich_menu_id: &str,
+    ) -> MessagingResult<String> {
+        self.with_bot_once(channel_user_id, |bot| {
+            crate::rich_menu::copy(bot, rich_menu_id)
+        })
+    }
+
+    pub fn get_linked_rich_menu(
+        &self,
+        channel_user_id: &str,
+        user_id: &str,
+    ) -> MessagingResult<Option<String>> {
+        self.with_bot(channel_user_id, |bot| {
+            crate::rich_menu::get_linked(bot, user_id)
+        })
+    }
+
+    pub fn bulk_link_rich_menu(
+        &self,
+        channel_user_id: &str,
+        user_ids: Vec<String>,
+        rich_menu_id: &str,
+    ) -> MessagingResult<String> {
+        self.with_bot(channel_user_id, |bot| {
+            crate::rich_menu::bulk_link(bot, &user_ids, rich_menu_id)
+        })
+    }
+
+    pub fn validate_reply_message(
+        &self,
+        channel_user_id: &str,
+        messages: &[ReplyMessage],
+    ) -> MessagingResult<()> {
+        self.with_bot(channel_user_id, |bot| {
+            crate::message::validate_reply(bot, messages)
+        })
+    }
+
+    pub fn get_message_quota(&self, channel_user_id: &str) -> MessagingResult<MessageQuota> {
+        self.with_bot(channel_user_id, crate::message::get_quota)
+    }
+
+    pub fn get_message_quota_consumption(
+        &self,
+        channel_user_id: &str,
+    ) -> MessagingResult<MessageQuotaConsumption> {
+        self.with_bot(channel_user_id, crate::message::get_quota_consumption)
+    }
+
+    pub fn get_aggregate_message_statistics(
+        &self,
+        channel_user_id: &str,
+        request_id: &str,
+    ) -> MessagingResult<AggregateMessageStats> {
+        self.with_bot(channel_user_id, |bot| {
+            crate::insight::get_aggregate_message_statistics(bot, request_id)
+        })
+    }
+
+    pub fn send_loading_animation(
+        &self,
+        channel_user_id: &str,
+        target_user_id: &str,
+        loading_seconds: u8,
+    ) -> MessagingResult<()> {
+        self.with_bot(channel_user_id, |bot| {
+            crate::message::start_loading_animation(bot, target_user_id, loading_seconds)
+        })
+    }
+
+    pub fn mark_messages_as_read(
+        &self,
+        channel_user_id: &str,
+        chat_id: &str,
+    ) -> MessagingResult<()> {
+        self.with_bot(channel_user_id, |bot| {
+            crate::message::mark_as_read(bot, chat_id)
+        })
+    }
+
+    // 外部ストレージにキャッシュしたトークンが有効かどうかを確認する。
+    pub fn verify_access_token(&self, access_token: &str) -> MessagingResult<VerifyTokenResponse> {
+        Ok(crate::oauth::verify_access_token(
+            self.pool.client(),
+            &self.pool.config().channel_defaults.base_url,
+            access_token,
+        )?)
+    }
+
+    // 発行したトークンはチャンネルには保持しないため、呼び出し側で管理すること。
+    #[cfg(feature = "v2_1_auth")]
+    pub fn create_channel_access_token_v2_1(
+        &self,
+        channel_id: &str,
+        private_key_pem: &str,
+        kid: &str,
+    ) -> MessagingResult<String> {
+        Ok(crate::oauth::issue_access_token_v2_1(
+            self.pool.client(),
+            &self.pool.config().channel_defaults.base_url,
+            channel_id,
+            private_key_pem,
+            kid,
+        )?)
+    }
+
+    pub fn push(
+        &self,
+        channel_user_id: &str,
+        to: &str,
+        messages: &[ReplyMessage],
+    ) -> MessagingResult<()> {
+        // 再試行したリクエストをサーバー側で重複として破棄できるよう、再試行を跨いで同一のリトライキーを送信する。
+        let retry_key = Uuid::new_v4().to_string();
+        self.with_bot(channel_user_id, |bot| {
+            crate::message::push(bot, to, messages, &retry_key)
+        })
+    }
+
+    pub fn push_text(
+        &self,
+        channel_user_id: &str,
+        to: &str,
+        text: impl Into<String>,
+    ) -> MessagingResult<()> {
+        self.push(channel_user_id, to, &[ReplyMessage::from(text.into())])
+    }
+
+    pub fn broadcast(
+        &self,
+        channel_user_id: &str,
+        messages: &[ReplyMessage],
+    ) -> MessagingResult<()> {
+        let retry_key = Uuid::new_v4().to_string();
+        self.with_bot(channel_user_id, |bot| {
+            crate::message::broadcast(bot, messages, &retry_key)
+        })
+    }
+
+    pub fn broadcast_text(
+        &self,
+        channel_user_id: &str,
+        text: impl Into<String>,
+    ) -> MessagingResult<()> {
+        self.broadcast(channel_user_id, &[ReplyMessage::from(text.into())])
+    }
+
+    // 流量制限を避けるため、設定された間隔を空けてユーザー毎に順次プッシュする。
+    pub fn push_batch(
+        &self,
+        channel_user_id: &str,
+        messages_by_user: HashMap<String, Vec<ReplyMessage>>,
+    ) -> Vec<(String, MessagingResult<()>)> {
+        let interval = self.pool.config().push_interval;
+        let mut results = Vec::with_capacity(messages_by_user.len());
+        for (i, (to, messages)) in messages_by_user.into_iter().enumerate() {
+            if i > 0 {
+                thread::sleep(interval);
+            }
+            let result = self.push(channel_user_id, &to, &messages);
+            if let Err(e) = &result {
+                error!(
+                    "プッシュメッセージの送信に失敗しました。宛先[{}], エラー[{:?}]",
+                    to, e
+                );
+            }
+            results.push((to, result));
+        }
+        results
+    }
+
+    // チャンネルのアクセストークンで認証したクライアントでMessaging APIを呼び出す。
+    fn with_bot<T>(
+        &self,
+        channel_user_id: &str,
+        f: impl Fn(&BotClient) -> BotResult<T>,
+    ) -> MessagingResult<T> {
+        let entry = self.get_channel(channel_user_id)?;
+        with_retry(entry.config.max_retries, || {
+            let token = entry.access_token()?;
+            Ok(f(&BotClient::new(&entry.client, &entry.config, &token))?)
+        })
+    }
+
+    // 冪等でないリクエストは、通信エラー等で処理済みか判別できないまま重複して実行しないよう再試行しない。
+    fn with_bot_once<T>(
+        &self,
+        channel_user_id: &str,
+        f: impl Fn(&BotClient) -> BotResult<T>,
+    ) -> MessagingResult<T> {
+        let entry = self.get_channel(channel_user_id)?;
+        let token = with_retry(entry.config.max_retries, || entry.access_token())?;
+        Ok(f(&BotClient::new(&entry.client, &entry.config, &token))?)
+    }
+}
+
+// 非同期コードから呼び出すためのエントリポイント。
+// ハンドラのロックは.awaitで待機し、reqwestによるブロッキングな通信はspawn_blockingで実行する。
+#[cfg(feature = "tokio")]
+impl MessagingApi {
+    pub async fn sign_async(
+        &self,
+        message: String,
+        digest: &[u8],
+    ) -> MessagingResult<WebhookEventBatch> {
+        let body = self.parse_body(message)?;
+        debug!("webhookリクエストの署名検証を行います。");
+        let entry = self.get_channel(&body.destination)?;
+        let secret = entry.secret.read().unwrap();
+        Self::verify_signature(&secret, body, digest)
+    }
+
+    pub async fn handle_event_async(
+        &self,
+        batch: WebhookEventBatch,
+    ) -> MessagingResult<Vec<MessagingResult<()>>> {
+        let results = self.handle_event_reporting_async(batch).await?;
+        Ok(results.into_iter().map(|(_, result)| result).collect())
+    }
+
+    pub async fn handle_event_reporting_async(
+        &self,
+        batch: WebhookEventBatch,
+    ) -> MessagingResult<Vec<(WebhookEvent, MessagingResult<()>)>> {
+        debug!("webhookイベントのハンドリングを行います。");
+        // ルーティングしない場合は、宛先のチャンネルが存在しなければバッチ全体をエラーとする。
+        if self.router.is_none() {
+            self.get_channel(&batch.destination)?;
+        }
+        let mut results = Vec::with_capacity(batch.events.len());
+        for event in batch.events {
+            let user_id = self.route(&event, &batch.destination);
+            let result = match self.get_channel(&user_id) {
+                Ok(entry) => {
+                    Self::handle_single_event_async(entry, &batch.destination, &event).await
+                }
+                Err(e) => Err(e),
+            };
+            if let Err(e) = &result {
+                error!(
+                    "webhookイベントのハンドリングに失敗しました。ユーザーID[{}], エラー[{:?}]",
+                    user_id, e
+                );
+            }
+            results.push((event, result));
+        }
+        Ok(results)
+    }
+
+    pub async fn sign_and_handle_async(
+        &self,
+        body: String,
+        signature: &[u8],
+    ) -> MessagingResult<Vec<MessagingResult<()>>> {
+        let batch = self.sign_async(body, signature).await?;
+        self.handle_event_async(batch).await
+    }
+
+    pub async fn update_handler_async(
+        &self,
+        channel_user_id: &str,
+        handler: impl HandleWebhookEvent + Send + 'static,
+    ) -> MessagingResult<()> {
+        let entry = self.get_channel(channel_user_id)?;
+        debug!(
+            "webhookハンドラを差し替えます。ユーザーID[{}]",
+            channel_user_id
+        );
+        *entry.handler.lock().await = Box::new(handler);
+        Ok(())
+    }
+
+    pub async fn respond_to_async(
+        &self,
+        channel_user_id: &str,
+        reply_token: &str,
+        messages: Vec<ReplyMessage>,
+        notification_disabled: bool,
+    ) -> MessagingResult<()> {
+        let reply = Arc::new(Reply::new_with_token(
+            reply_token,
+            messages,
+            notification_disabled,
+        ));
+        let entry = self.get_channel(channel_user_id)?;
+        Self::respond_async(entry, reply).await
+    }
+
+    async fn handle_single_event_async(
+        entry: Arc<ChannelEntry>,
+        destination: &str,
+        event: &WebhookEvent,
+    ) -> MessagingResult<()> {
+        let reply = {
+            let mut handler = entry.handler.lock().await;
+            call_handler(&mut **handler, &entry, destination, event)?
+        };
+        if let Some(reply) = reply {
+            Self::respond_async(entry, Arc::new(reply)).await?;
+        }
+        Ok(())
+    }
+
+    async fn respond_async(entry: Arc<ChannelEntry>, reply: Arc<Reply>) -> MessagingResult<()> {
+        let mut attempt = 0;
+        loop {
+            let result = Self::try_respond_async(&entry, &reply).await;
+            match result {
+                Err(e) if e.is_retryable() && attempt < entry.config.max_retries => {
+                    attempt += 1;
+                    warn!(
+                        "リクエストに失敗したため再試行します。試行回数[{}], エラー[{:?}]",
+                        attempt, e
+                    );
+                    tokio::time::sleep(retry_delay(attempt, &e)).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn try_respond_async(
+        entry: &Arc<ChannelEntry>,
+        reply: &Arc<Reply>,
+    ) -> MessagingResult<()> {
+        let (entry, reply) = (Arc::clone(entry), Arc::clone(reply));
+        run_blocking(move || {
+            let token = entry.access_token()?;
+            Ok(respond(
+                &entry.client,
+                &entry.config.base_url,
+                &token,
+                &reply,
+            )?)
+        })
+        .await
+    }
+}
+
+// ブロッキングタスク内で発生したパニックは呼び出し元に伝播させる。
+#[cfg(feature = "tokio")]
+async fn run_blocking<T, F>(f: F) -> MessagingResult<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> MessagingResult<T> + Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(e) => panic::resume_unwind(e.into_panic()),
+    }
+}
+
+// ハンドラ内でのパニックが処理全体を巻き込まないようエラーに変換する。
+fn call_handler(
+    handler: &mut dyn HandleWebhookEvent,
+    entry: &ChannelEntry,
+    destination: &str,
+    event: &WebhookEvent,
+) -> MessagingResult<Option<Reply>> {
+    let line_event = LineEvent {
+        event,
+        channel_id: entry.id,
+        destination,
+    };
+    panic::catch_unwind(AssertUnwindSafe(|| invoke_handler(handler, &line_event))).map_err(
+        |payload| MessagingError::HandlerPanic {
+            message: panic_message(&*payload),
+        },
+    )
+}
+
+// 再試行の待機時間の初期値と上限。
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+
+// 再試行可能なエラーの場合、指数的に待機時間を延ばしながら最大max_retries回まで再試行する。
+fn with_retry<T>(
+    max_retries: u32,
+    mut f: impl FnMut() -> MessagingResult<T>,
+) -> MessagingResult<T> {
+    let mut attempt = 0;
+    loop {
+        match f() {
+            Err(e) if e.is_retryable() && attempt < max_retries => {
+                attempt += 1;
+                warn!(
+                    "リクエストに失敗したため再試行します。試行回数[{}], エラー[{:?}]",
+                    attempt, e
+                );
+                thread::sleep(retry_delay(attempt, &e));
+            }
+            result => return result,
+        }
+    }
+}
+
+// サーバーから待機時間を指定された場合はそれに従う。
+fn retry_delay(attempt: u32, error: &MessagingError) -> Duration {
+    if let MessagingError::OAuth {
+        error: OAuthError::RateLimited {
+            retry_after: Some(seconds),
+        },
+    } = error
+    {
+        return Duration::from_secs(*seconds);
+    }
+    let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+    RETRY_BASE_DELAY
+        .checked_mul(factor)
+        .map_or(RETRY_MAX_DELAY, |delay| delay.min(RETRY_MAX_DELAY))
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_owned()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "webhookハンドラでパニックが発生しました。".to_owned()
     }
 }
 
 pub type MessagingResult<T> = Result<T, MessagingError>;
 
-#[derive(Debug, Fail)]
+#[derive(Debug, Error)]
 pub enum MessagingError {
-    #[fail(display = "Destination error: {}", message)]
+    #[error("Destination error: {}", .message)]
     Destination { message: String },
-    #[fail(display = "Signature error: {}", message)]
-    Signature { message: String },
-    #[fail(display = "OAuth error: {}", error)]
-    OAuth { error: OAuthError },
-    #[fail(display = "Reply error: {}", error)]
-    Reply { error: ReplyError },
-    #[fail(display = "RequestBody error: {}", error)]
-    RequestBody { error: RequestBodyError },
+    #[error(
+        "Signature error: {}, channel user id: {}, digest: {}",
+        .message, .channel_user_id, .digest_hex
+    )]
+    Signature {
+        message: String,
+        channel_user_id: String,
+        digest_hex: String,
+    },
+    #[error("OAuth error")]
+    OAuth {
+        #[source]
+        error: OAuthError,
+    },
+    #[error("Reply error")]
+    Reply {
+        #[source]
+        error: ReplyError,
+    },
+    #[error("RequestBody error")]
+    RequestBody {
+        #[source]
+        error: RequestBodyError,
+    },
+    #[error("Handler panic: {}", .message)]
+    HandlerPanic { message: String },
+    #[error("Channel busy: {}", .message)]
+    ChannelBusy { message: String },
+    #[error("Bot error")]
+    Bot {
+        #[source]
+        error: BotError,
+    },
+}
+
+impl MessagingError {
+    // 通信エラー等の一時的な失敗であれば再試行の余地があるものとする。
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            MessagingError::OAuth {
+                error: OAuthError::Reqwest { .. },
+            } => true,
+            MessagingError::OAuth {
+                error: OAuthError::RateLimited { .. },
+            } => true,
+            MessagingError::Reply {
+                error: ReplyError::Reqwest { .. },
+            } => true,
+            MessagingError::Reply {
+                error: ReplyError::ApiError { status, .. },
+            } => *status == 429 || *status >= 500,
+            MessagingError::Bot {
+                error: BotError::Reqwest { .. },
+            } => true,
+            MessagingError::Bot {
+                error: BotError::ApiError { status, .. },
+            } => *status == 429 || *status >= 500,
+            // 認証情報の誤り等の設定起因のエラーは再試行しても解消しない。
+            _ => false,
+        }
+    }
 }
 
 impl From<OAuthError> for MessagingError {
     fn from(error: OAuthError) -> Self {
-        MessagingError::OAuth{error}
+        MessagingError::OAuth { error }
     }
 }
 
 impl From<ReplyError> for MessagingError {
     fn from(error: ReplyError) -> Self {
-        MessagingError::Reply{error}
+        MessagingError::Reply { error }
+    }
+}
+
+impl From<BotError> for MessagingError {
+    fn from(error: BotError) -> Self {
+        MessagingError::Bot { error }
     }
 }
 
 impl From<RequestBodyError> for MessagingError {
     fn from(error: RequestBodyError) -> Self {
-        MessagingError::RequestBody{error}
+        MessagingError::RequestBody { error }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ChannelConfig;
+    use crate::reply::text_reply;
+    use crate::test_util::blocking;
+    use serde_json::json;
+    use std::sync::{Mutex, OnceLock, Weak};
+    use wiremock::matchers::{
+        body_partial_json, body_string_contains, header, header_exists, method, path,
+    };
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const CHANNEL_USER_ID: &str = "U0123456789abcdef0123456789abcdef";
+
+    fn api_with_server(server: &MockServer, channel: Channel) -> MessagingApi {
+        let config = MessagingApiConfig {
+            channel_defaults: ChannelConfig {
+                base_url: server.uri(),
+                max_retries: 1,
+                ..ChannelConfig::default()
+            },
+            ..MessagingApiConfig::default()
+        };
+        MessagingApi::with_config(config).add_channel(channel)
+    }
+
+    fn noop_channel() -> Channel {
+        Channel::new(
+            1,
+            CHANNEL_USER_ID.parse().unwrap(),
+            "secret".to_owned(),
+            Some("token".to_owned()),
+            crate::channel::handler_fn(|_| None),
+        )
+    }
+
+    fn follow_batch() -> WebhookEventBatch {
+        WebhookEventBatch {
+            destination: CHANNEL_USER_ID.to_owned(),
+            events: vec![serde_json::from_str(
+                r#"{
+                    "replyToken": "nHuyWiB7yP5Zw52FIkcQobQuGDXCTA",
+                    "type": "follow",
+                    "timestamp": 1462629479859,
+                    "source": {"type": "user", "userId": "U4af4980629"}
+                }"#,
+            )
+            .unwrap()],
+            raw_events: Vec::new(),
+        }
+    }
+
+    async fn mount_profile(server: &MockServer, access_token: &str) {
+        let authorization = format!("Bearer {}", access_token);
+        Mock::given(method("GET"))
+            .and(path("/v2/bot/profile/U4af4980629"))
+            .and(header("Authorization", authorization.as_str()))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "displayName": "LINE taro",
+                "userId": "U4af4980629"
+            })))
+            .expect(1)
+            .mount(server)
+            .await;
+    }
+
+    #[test]
+    fn retry_delay_backs_off_exponentially() {
+        let error = MessagingError::HandlerPanic {
+            message: String::new(),
+        };
+        assert_eq!(retry_delay(1, &error), Duration::from_millis(500));
+        assert_eq!(retry_delay(3, &error), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn retry_delay_is_capped() {
+        let error = MessagingError::HandlerPanic {
+            message: String::new(),
+        };
+        assert_eq!(retry_delay(64, &error), RETRY_MAX_DELAY);
+        assert_eq!(retry_delay(u32::MAX, &error), RETRY_MAX_DELAY);
+    }
+
+    #[test]
+    fn retry_delay_honors_retry_after() {
+        let error = MessagingError::from(OAuthError::RateLimited {
+            retry_after: Some(30),
+        });
+        assert_eq!(retry_delay(1, &error), Duration::from_secs(30));
+    }
+
+    #[tokio::test]
+    async fn call_api_inside_handler() {
+        let server = MockServer::start().await;
+        mount_profile(&server, "token").await;
+        Mock::given(method("POST"))
+            .and(path("/v2/bot/message/reply"))
+            .and(body_partial_json(json!({
+                "replyToken": "nHuyWiB7yP5Zw52FIkcQobQuGDXCTA",
+                "messages": [{"type": "text", "text": "LINE taro"}]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        // ハンドラの生成時にはMessagingApiが存在しないため、生成後に設定する。
+        let api_cell: Arc<OnceLock<Weak<MessagingApi>>> = Arc::new(OnceLock::new());
+        let handler_api = Arc::clone(&api_cell);
+        let channel = Channel::new(
+            1,
+            CHANNEL_USER_ID.parse().unwrap(),
+            "secret".to_owned(),
+            Some("token".to_owned()),
+            crate::channel::handler_fn(move |_| {
+                let api = handler_api.get().unwrap().upgrade().unwrap();
+                let profile = api.get_profile(CHANNEL_USER_ID, "U4af4980629").unwrap();
+                Some(text_reply(profile.display_name))
+            }),
+        );
+        let api = Arc::new(api_with_server(&server, channel));
+        api_cell.set(Arc::downgrade(&api)).unwrap();
+
+        let results = blocking(move || api.handle_event(follow_batch()))
+            .await
+            .unwrap();
+        assert!(results.iter().all(Result::is_ok));
+    }
+
+    #[tokio::test]
+    async fn update_secret_of_registered_channel() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v2/oauth/accessToken"))
+            .and(body_string_contains("client_secret=new-secret"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "access_token": "new-token",
+                "expires_in": 2592000,
+                "token_type": "Bearer"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        mount_profile(&server, "new-token").await;
+        let channel = Channel::new(
+            1,
+            CHANNEL_USER_ID.parse().unwrap(),
+            "old-secret".to_owned(),
+            Some("old-token".to_owned()),
+            crate::channel::handler_fn(|_| None),
+        );
+        let api = api_with_server(&server, channel);
+
+        api.update_secret(CHANNEL_USER_ID, "new-secret".to_owned())
+            .unwrap();
+        let profile = blocking(move || api.get_profile(CHANNEL_USER_ID, "U4af4980629"))
+            .await
+            .unwrap();
+        assert_eq!(profile.display_name, "LINE taro");
+    }
+
+    #[test]
+    fn update_handler_of_registered_channel() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let (first, second) = (Arc::clone(&calls), Arc::clone(&calls));
+        let channel = Channel::new(
+            1,
+            CHANNEL_USER_ID.parse().unwrap(),
+            "secret".to_owned(),
+            Some("token".to_owned()),
+            crate::channel::handler_fn(move |_| {
+                first.lock().unwrap().push("first");
+                None
+            }),
+        );
+        let api = MessagingApi::new().add_channel(channel);
+
+        api.handle_event(follow_batch()).unwrap();
+        api.update_handler(
+            CHANNEL_USER_ID,
+            crate::channel::handler_fn(move |_| {
+                second.lock().unwrap().push("second");
+                None
+            }),
+        )
+        .unwrap();
+        api.handle_event(follow_batch()).unwrap();
+        assert_eq!(*calls.lock().unwrap(), ["first", "second"]);
+    }
+
+    #[test]
+    fn is_token_expired_of_registered_channel() {
+        let channel = |user_id: &str, access_token: Option<&str>| {
+            Channel::new(
+                1,
+                user_id.parse().unwrap(),
+                "secret".to_owned(),
+                access_token.map(str::to_owned),
+                crate::channel::handler_fn(|_| None),
+            )
+        };
+        let api = MessagingApi::new()
+            .add_channel(channel(CHANNEL_USER_ID, Some("token")))
+            .add_channel(channel("U00000000000000000000000000000000", None));
+
+        assert!(!api.is_token_expired(CHANNEL_USER_ID).unwrap());
+        assert!(api
+            .is_token_expired("U00000000000000000000000000000000")
+            .unwrap());
+        assert!(matches!(
+            api.is_token_expired("Uffffffffffffffffffffffffffffffff"),
+            Err(MessagingError::Destination { .. })
+        ));
+    }
+
+    #[test]
+    fn route_events_with_map() {
+        const GROUP_CHANNEL_USER_ID: &str = "U00000000000000000000000000000000";
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let channel = |user_id: &str, name: &'static str| {
+            let calls = Arc::clone(&calls);
+            Channel::new(
+                1,
+                user_id.parse().unwrap(),
+                "secret".to_owned(),
+                Some("token".to_owned()),
+                crate::channel::handler_fn(move |event| {
+                    calls
+                        .lock()
+                        .unwrap()
+                        .push((name, event.destination.to_owned()));
+                    None
+                }),
+            )
+        };
+        let routes: HashMap<String, String> = vec![(
+            "Ca56f94637c4bd7d3a5e7c2e4d7b4c1a".to_owned(),
+            GROUP_CHANNEL_USER_ID.to_owned(),
+        )]
+        .into_iter()
+        .collect();
+        let api = MessagingApi::new()
+            .add_channel(channel(CHANNEL_USER_ID, "default"))
+            .add_channel(channel(GROUP_CHANNEL_USER_ID, "group"))
+            .with_router(move |event| {
+                let chat_id = event.common_property().source.chat_id();
+                routes.get(chat_id).cloned()
+            });
+        let mut batch = follow_batch();
+        batch.events.push(
+            serde_json::from_str(
+                r#"{
+                    "replyToken": "8cf9239d56244f4197887e939187e19e",
+                    "type": "join",
+                    "timestamp": 1462629479859,
+                    "source": {"type": "group", "groupId": "Ca56f94637c4bd7d3a5e7c2e4d7b4c1a"}
+                }"#,
+            )
+            .unwrap(),
+        );
+
+        let results = api.handle_event(batch).unwrap();
+        assert!(results.iter().all(Result::is_ok));
+        // ルーティングしたイベントにも、リクエストの宛先を渡す。
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                ("default", CHANNEL_USER_ID.to_owned()),
+                ("group", CHANNEL_USER_ID.to_owned())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn push_retries_with_retry_key() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v2/bot/message/push"))
+            .and(header_exists("X-Line-Retry-Key"))
+            .respond_with(ResponseTemplate::new(500).set_body_json(json!({
+                "message": "An unexpected error occurred"
+            })))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        // 初回のリクエストが受理済みの場合、再試行したリクエストは409となる。
+        Mock::given(method("POST"))
+            .and(path("/v2/bot/message/push"))
+            .and(header_exists("X-Line-Retry-Key"))
+            .respond_with(ResponseTemplate::new(409).set_body_json(json!({
+                "message": "The retry key is already accepted"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let api = api_with_server(&server, noop_channel());
+
+        let result = blocking(move || api.push_text(CHANNEL_USER_ID, "U4af4980629", "hello")).await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn issue_link_token_is_not_retried() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v2/bot/user/U4af4980629/linkToken"))
+            .respond_with(ResponseTemplate::new(500).set_body_json(json!({
+                "message": "An unexpected error occurred"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let api = api_with_server(&server, noop_channel());
+
+        let result = blocking(move || api.issue_link_token(CHANNEL_USER_ID, "U4af4980629")).await;
+        assert!(matches!(
+            result,
+            Err(MessagingError::Bot {
+                error: BotError::ApiError { status: 500, .. }
+            })
+        ));
+    }
+}
+
+#[cfg(all(test, feature = "tokio"))]
+mod tokio_tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const CHANNEL_USER_ID: &str = "U0123456789abcdef0123456789abcdef";
+
+    struct CountingHandler(Arc<AtomicUsize>);
+
+    impl HandleWebhookEvent for CountingHandler {
+        fn handle_webhook_event(&mut self, _: &LineEvent) -> Option<Reply> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            None
+        }
+    }
+
+    fn api_and_batch() -> (MessagingApi, WebhookEventBatch, Arc<AtomicUsize>) {
+        let count = Arc::new(AtomicUsize::new(0));
+        let channel = Channel::new(
+            1,
+            CHANNEL_USER_ID.parse().unwrap(),
+            "secret".to_owned(),
+            Some("token".to_owned()),
+            CountingHandler(Arc::clone(&count)),
+        );
+        let event = serde_json::from_str(
+            r#"{
+                "type": "follow",
+                "timestamp": 1462629479859,
+                "source": {"type": "user", "userId": "U4af4980629..."}
+            }"#,
+        )
+        .unwrap();
+        let batch = WebhookEventBatch {
+            destination: CHANNEL_USER_ID.to_owned(),
+            events: vec![event],
+            raw_events: Vec::new(),
+        };
+        (MessagingApi::new().add_channel(channel), batch, count)
+    }
+
+    #[tokio::test]
+    async fn handle_event_inside_runtime() {
+        let (api, batch, count) = api_and_batch();
+        let results = api.handle_event(batch).unwrap();
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn handle_event_inside_runtime_while_locked() {
+        let (api, batch, count) = api_and_batch();
+        let entry = api.get_channel(CHANNEL_USER_ID).unwrap();
+        let _guard = entry.handler.lock().await;
+        let result = api.handle_event(batch);
+        assert!(matches!(result, Err(MessagingError::ChannelBusy { .. })));
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn update_handler_async() {
+        let (api, batch, count) = api_and_batch();
+        let replaced = Arc::new(AtomicUsize::new(0));
+        api.update_handler_async(CHANNEL_USER_ID, CountingHandler(Arc::clone(&replaced)))
+            .await
+            .unwrap();
+        api.handle_event_async(batch).await.unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+        assert_eq!(replaced.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn handle_event_async() {
+        let (api, batch, count) = api_and_batch();
+        let results = api.handle_event_async(batch).await.unwrap();
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(count.load(Ordering::SeqCst), 1);
     }
 }
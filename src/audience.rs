@@ -0,0 +1,45 @@
+use crate::bot::{self, BotClient, BotResult};
+use log::debug;
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AudienceGroupPage {
+    pub audience_groups: Vec<AudienceGroupSummary>,
+    pub total_count: u64,
+    pub page: u32,
+    pub size: u32,
+    pub has_next_page: bool,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AudienceGroupSummary {
+    pub audience_group_id: u64,
+    #[serde(rename = "type")]
+    pub group_type: String,
+    pub description: String,
+    pub status: String,
+    pub failed_type: Option<String>,
+    pub audience_count: u64,
+    pub created: u64,
+    pub request_id: Option<String>,
+    pub click_url: Option<String>,
+}
+
+pub(crate) fn list_audience_groups(
+    bot: &BotClient,
+    page: u32,
+    description: Option<&str>,
+) -> BotResult<AudienceGroupPage> {
+    debug!("オーディエンス一覧の取得リクエストを行います。ページ[{}]", page);
+    let mut query = vec![("page", page.to_string())];
+    if let Some(description) = description {
+        query.push(("description", description.to_owned()));
+    }
+    let mut res = bot::send(
+        bot.get("/v2/bot/audienceGroup/list")
+            .query(&query),
+    )?;
+    Ok(res.json()?)
+}
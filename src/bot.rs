@@ -0,0 +1,136 @@
+use crate::config::ChannelConfig;
+use crate::reply::{ErrorResponseBody, LineErrorDetail};
+use log::{debug, error};
+use reqwest::{header, Client, RequestBuilder, Response};
+use thiserror::Error;
+
+// チャンネルアクセストークンで認証するMessaging APIリクエストを組み立てる。
+// パスはチャンネル設定のベースURLからの相対パスで指定する。
+pub(crate) struct BotClient<'a> {
+    client: &'a Client,
+    config: &'a ChannelConfig,
+    access_token: &'a str,
+}
+
+impl<'a> BotClient<'a> {
+    pub(crate) fn new(
+        client: &'a Client,
+        config: &'a ChannelConfig,
+        access_token: &'a str,
+    ) -> Self {
+        BotClient {
+            client,
+            config,
+            access_token,
+        }
+    }
+
+    pub(crate) fn get(&self, path: &str) -> RequestBuilder {
+        self.authorize(
+            self.client
+                .get(&format!("{}{}", self.config.base_url, path)),
+        )
+    }
+
+    pub(crate) fn get_data(&self, path: &str) -> RequestBuilder {
+        self.authorize(
+            self.client
+                .get(&format!("{}{}", self.config.data_base_url, path)),
+        )
+    }
+
+    pub(crate) fn post(&self, path: &str) -> RequestBuilder {
+        self.authorize(
+            self.client
+                .post(&format!("{}{}", self.config.base_url, path)),
+        )
+    }
+
+    pub(crate) fn delete(&self, path: &str) -> RequestBuilder {
+        self.authorize(
+            self.client
+                .delete(&format!("{}{}", self.config.base_url, path)),
+        )
+    }
+
+    fn authorize(&self, builder: RequestBuilder) -> RequestBuilder {
+        builder.header(
+            header::AUTHORIZATION,
+            format!("Bearer {}", self.access_token),
+        )
+    }
+}
+
+pub(crate) fn send(request: RequestBuilder) -> BotResult<Response> {
+    let mut res = request.send()?;
+    if res.status().is_success() {
+        debug!(
+            "Messaging APIのリクエストに成功しました。ステータス[{}]",
+            res.status()
+        );
+        Ok(res)
+    } else {
+        let e_res_body = ErrorResponseBody::parse(res.text()?);
+        error!(
+            "Messaging APIのリクエストに失敗しました。ステータス[{}], エラーレスポンス[{:?}]",
+            res.status(),
+            e_res_body
+        );
+        Err(BotError::ApiError {
+            status: u16::from(res.status()),
+            message: e_res_body.message,
+            details: e_res_body.details,
+        })
+    }
+}
+
+// 同一のリトライキーのリクエストが受理済みの場合は409となるため、送信済みとして扱う。
+pub(crate) fn send_with_retry_key(request: RequestBuilder, retry_key: &str) -> BotResult<()> {
+    match send(request.header("X-Line-Retry-Key", retry_key)) {
+        Ok(_) => Ok(()),
+        Err(BotError::ApiError { status: 409, .. }) => {
+            debug!(
+                "リトライキーのリクエストは受理済みです。リトライキー[{}]",
+                retry_key
+            );
+            Ok(())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+pub(crate) fn request_id(res: &Response) -> String {
+    res.headers()
+        .get("x-line-request-id")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_owned()
+}
+
+pub(crate) type BotResult<T> = Result<T, BotError>;
+
+#[derive(Debug, Error)]
+pub enum BotError {
+    #[error("Request error")]
+    Reqwest {
+        #[source]
+        error: reqwest::Error,
+    },
+    #[error(
+        "API error: status = {}, message = {}, details = {:?}",
+        .status, .message, .details
+    )]
+    ApiError {
+        status: u16,
+        message: String,
+        details: Vec<LineErrorDetail>,
+    },
+    #[error("Invalid argument: {}", .message)]
+    InvalidArgument { message: String },
+}
+
+impl From<reqwest::Error> for BotError {
+    fn from(error: reqwest::Error) -> Self {
+        BotError::Reqwest { error }
+    }
+}
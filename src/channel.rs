@@ -1,23 +1,111 @@
+use crate::api::MessagingResult;
+use crate::config::{build_client, ChannelConfig};
 use crate::event::WebhookEvent;
 use crate::reply::Reply;
 use log::debug;
+use reqwest::Client;
+use std::borrow::Borrow;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+// 有効期限の間際のトークンで失敗しないよう、期限の60秒前から期限切れとみなす。
+const TOKEN_EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+// マルチテナントのボットがイベントを受信したチャンネルを判別できるよう、チャンネルの情報を添える。
+pub struct LineEvent<'a> {
+    pub event: &'a WebhookEvent,
+    pub channel_id: usize,
+    // webhookリクエストの宛先のユーザーID。ルーティングした場合は処理するチャンネルと異なり得る。
+    pub destination: &'a str,
+}
 
 pub trait HandleWebhookEvent {
-    fn handle_webhook_event(&mut self, event: &WebhookEvent) -> Option<Reply>;
+    fn handle_webhook_event(&mut self, event: &LineEvent) -> Option<Reply>;
+}
+
+// 簡単なボットでは構造体を定義せず、クロージャをそのままハンドラとして渡せるようにする。
+impl<F> HandleWebhookEvent for F
+where
+    F: FnMut(&LineEvent) -> Option<Reply>,
+{
+    fn handle_webhook_event(&mut self, event: &LineEvent) -> Option<Reply> {
+        self(event)
+    }
+}
+
+// 引数の型を注釈しないクロージャをChannel::new等に直接渡すと、引数の参照の寿命を推論できずにコンパイルエラーとなる。
+// `|event: &LineEvent| ..`のように引数の型を注釈するか、この関数で包んでから渡すこと。
+pub fn handler_fn<F>(f: F) -> F
+where
+    F: FnMut(&LineEvent) -> Option<Reply>,
+{
+    f
+}
+
+// ユーザーIDは「U」と32桁の16進数からなる。
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UserId(String);
+
+impl UserId {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for UserId {
+    type Err = InvalidUserIdError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let valid = s.len() == 33
+            && s.starts_with('U')
+            && s[1..].chars().all(|c| c.is_ascii_hexdigit());
+        if valid {
+            Ok(UserId(s.to_owned()))
+        } else {
+            Err(InvalidUserIdError {
+                value: s.to_owned(),
+            })
+        }
+    }
+}
+
+// マップのキーとして&strで検索できるよう、strとして借用できるようにする。
+impl Borrow<str> for UserId {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for UserId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("Invalid user id: {}", .value)]
+pub struct InvalidUserIdError {
+    pub value: String,
 }
 
 pub struct Channel {
     pub(crate) id: usize,
-    pub(crate) user_id: String,
+    pub(crate) user_id: UserId,
     pub(crate) secret: String,
-    pub(crate) access_token: Option<String>,
-    handler: Box<dyn HandleWebhookEvent + Send + 'static>,
+    pub(crate) token: AccessToken,
+    pub(crate) config: Option<ChannelConfig>,
+    // 個別設定のタイムアウトを適用するため、設定毎にクライアントを保持する。
+    pub(crate) client: Option<Client>,
+    pub(crate) handler: Handler,
 }
 
+pub(crate) type Handler = Box<dyn HandleWebhookEvent + Send + 'static>;
+
 impl Channel {
     pub fn new(
         id: usize,
-        user_id: String,
+        user_id: UserId,
         secret: String,
         access_token: Option<String>,
         handler: impl HandleWebhookEvent + Send + 'static,
@@ -27,21 +115,161 @@ impl Channel {
             id,
             user_id,
             secret,
-            access_token,
+            token: AccessToken {
+                value: access_token,
+                expires_at: None,
+            },
+            config: None,
+            client: None,
             handler,
         }
     }
 
-    pub(crate) fn handle_event(&mut self, event: WebhookEvent) -> Option<Reply> {
-        match self.handler.handle_webhook_event(&event) {
-            Some(mut reply) => {
-                if let Some(token) = event.get_reply_token() {
-                    reply.reply_token = token;
-                }
-                debug!("webhookハンドラからリプライオブジェクトを受信しました。");
-                Some(reply)
+    pub fn with_config(mut self, config: ChannelConfig) -> Self {
+        self.client = Some(build_client(&config));
+        self.config = Some(config);
+        self
+    }
+
+    pub fn update_secret(&mut self, new_secret: String) {
+        debug!("チャンネルシークレットを更新します。ユーザーID[{}]", self.user_id);
+        self.secret = new_secret;
+        // 旧シークレットで発行したアクセストークンは破棄する。
+        self.token = AccessToken::default();
+    }
+
+    pub fn is_token_expired(&self) -> bool {
+        self.token.is_expired()
+    }
+
+    pub fn update_handler(&mut self, handler: impl HandleWebhookEvent + Send + 'static) {
+        debug!("webhookハンドラを差し替えます。ユーザーID[{}]", self.user_id);
+        self.handler = Box::new(handler);
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct AccessToken {
+    pub(crate) value: Option<String>,
+    pub(crate) expires_at: Option<Instant>,
+}
+
+impl AccessToken {
+    // 有効期限が不明なトークンは有効とみなす。
+    pub(crate) fn is_expired(&self) -> bool {
+        match (&self.value, self.expires_at) {
+            (None, _) => true,
+            (Some(_), Some(expires_at)) => expires_at <= Instant::now() + TOKEN_EXPIRY_MARGIN,
+            (Some(_), None) => false,
+        }
+    }
+
+    pub(crate) fn ensure(
+        &mut self,
+        client: &Client,
+        base_url: &str,
+        channel_id: usize,
+        secret: &str,
+    ) -> MessagingResult<&str> {
+        if self.is_expired() {
+            // アクセストークンが無い、または期限切れのため新規に発番する。
+            let (token, expires_at) =
+                crate::oauth::issue_access_token(client, base_url, channel_id, secret)?;
+            self.value = Some(token);
+            self.expires_at = Some(expires_at);
+        } else {
+            debug!("既存のアクセストークンを使用します。チャンネルID[{}]", channel_id);
+        }
+        Ok(self.value.as_ref().unwrap())
+    }
+}
+
+// ハンドラを呼び出し、返されたリプライにイベントのリプライトークンを設定する。
+pub(crate) fn invoke_handler(
+    handler: &mut dyn HandleWebhookEvent,
+    event: &LineEvent,
+) -> Option<Reply> {
+    match handler.handle_webhook_event(event) {
+        Some(mut reply) => {
+            if let Some(token) = event.event.get_reply_token() {
+                reply.reply_token = token;
             }
-            None => None,
+            debug!("webhookハンドラからリプライオブジェクトを受信しました。");
+            Some(reply)
+        }
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reply::text_reply;
+
+    fn follow_event() -> WebhookEvent {
+        serde_json::from_str(
+            r#"{
+                "replyToken": "nHuyWiB7yP5Zw52FIkcQobQuGDXCTA",
+                "type": "follow",
+                "timestamp": 1462629479859,
+                "source": {"type": "user", "userId": "U4af4980629..."}
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn reply_to(channel: &mut Channel, event: &WebhookEvent) -> Option<Reply> {
+        let line_event = LineEvent {
+            event,
+            channel_id: channel.id,
+            destination: channel.user_id.as_str(),
+        };
+        invoke_handler(&mut *channel.handler, &line_event)
+    }
+
+    #[test]
+    fn channel_with_closure_handler() {
+        let mut channel = Channel::new(
+            1,
+            "U0123456789abcdef0123456789abcdef".parse().unwrap(),
+            "secret".to_owned(),
+            None,
+            handler_fn(|event| event.event.is_from_user().then(|| text_reply("Hello"))),
+        );
+        let reply = reply_to(&mut channel, &follow_event()).unwrap();
+        assert_eq!(reply.reply_token, "nHuyWiB7yP5Zw52FIkcQobQuGDXCTA");
+    }
+
+    #[test]
+    fn channel_with_annotated_closure_handler() {
+        let mut channel = Channel::new(
+            1,
+            "U0123456789abcdef0123456789abcdef".parse().unwrap(),
+            "secret".to_owned(),
+            None,
+            |event: &LineEvent| event.event.is_from_user().then(|| text_reply("Hello")),
+        );
+        let reply = reply_to(&mut channel, &follow_event()).unwrap();
+        assert_eq!(reply.reply_token, "nHuyWiB7yP5Zw52FIkcQobQuGDXCTA");
+    }
+
+    #[test]
+    fn parse_valid_user_id() {
+        let user_id: UserId = "U0123456789abcdef0123456789ABCDEF".parse().unwrap();
+        assert_eq!(user_id.as_str(), "U0123456789abcdef0123456789ABCDEF");
+    }
+
+    #[test]
+    fn parse_invalid_user_id() {
+        for value in [
+            "",
+            "U0123456789abcdef0123456789abcde",
+            "U0123456789abcdef0123456789abcdef0",
+            "C0123456789abcdef0123456789abcdef",
+            "U0123456789abcdef0123456789abcdeg",
+        ] {
+            let error = value.parse::<UserId>().unwrap_err();
+            assert_eq!(error.value, value);
         }
     }
 }
@@ -0,0 +1,61 @@
+use reqwest::Client;
+use std::time::Duration;
+
+// HTTPクライアントの設定はreqwest 0.9のClientBuilderで設定可能な項目に限られる。
+// TCPキープアライブ・アイドルタイムアウトは設定できないため、アイドル状態のコネクションは
+// サーバー側で切断されるまでプールに残り、切断済みのコネクションを再利用したリクエストは通信エラーとなり得る。
+// 通信エラーは再試行可能なエラーのため、max_retriesを1以上に設定して対処すること。
+#[derive(Debug, Clone)]
+pub struct ChannelConfig {
+    // 接続から応答の受信完了までのリクエスト全体のタイムアウト（ClientBuilder::timeout）。
+    pub request_timeout: Duration,
+    pub max_retries: u32,
+    pub base_url: String,
+    // メッセージコンテンツ等の取得に使用するデータ用ドメインのURL。
+    pub data_base_url: String,
+    // ホスト毎に保持するアイドル状態のコネクション数の上限（ClientBuilder::max_idle_per_host）。
+    pub pool_max_idle_per_host: usize,
+}
+
+impl Default for ChannelConfig {
+    fn default() -> Self {
+        ChannelConfig {
+            request_timeout: Duration::from_secs(30),
+            max_retries: 0,
+            base_url: "https://api.line.me".to_owned(),
+            data_base_url: "https://api-data.line.me".to_owned(),
+            pool_max_idle_per_host: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MessagingApiConfig {
+    // チャンネル個別の設定が無い場合に使用する設定。
+    pub channel_defaults: ChannelConfig,
+    // イベントが空のwebhookリクエストを署名検証時にエラーとする。
+    pub reject_empty_events: bool,
+    // 一括プッシュ時の流量制限を避けるためのリクエスト間隔。
+    pub push_interval: Duration,
+    // プロフィールの一括取得時に並行して行うリクエスト数の上限。
+    pub profile_concurrency: usize,
+}
+
+impl Default for MessagingApiConfig {
+    fn default() -> Self {
+        MessagingApiConfig {
+            channel_defaults: ChannelConfig::default(),
+            reject_empty_events: false,
+            push_interval: Duration::from_millis(50),
+            profile_concurrency: 10,
+        }
+    }
+}
+
+pub(crate) fn build_client(config: &ChannelConfig) -> Client {
+    Client::builder()
+        .timeout(config.request_timeout)
+        .max_idle_per_host(config.pool_max_idle_per_host)
+        .build()
+        .expect("HTTPクライアントの初期化に失敗しました。")
+}
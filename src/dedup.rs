@@ -0,0 +1,136 @@
+use crate::channel::{HandleWebhookEvent, LineEvent};
+use crate::reply::Reply;
+use log::debug;
+use std::collections::{HashSet, VecDeque};
+
+// webhookの再送は短時間で行われるため、直近のイベントIDを保持すれば十分とする。
+const DEFAULT_CAPACITY: usize = 10_000;
+
+pub trait EventIdStore {
+    fn is_seen(&mut self, id: &str) -> bool;
+    fn mark_seen(&mut self, id: &str);
+}
+
+// 上限件数を超えた場合は古いイベントIDから破棄する。
+pub struct HashSetEventIdStore {
+    ids: HashSet<String>,
+    order: VecDeque<String>,
+    capacity: usize,
+}
+
+impl HashSetEventIdStore {
+    pub fn new() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        HashSetEventIdStore {
+            ids: HashSet::new(),
+            order: VecDeque::new(),
+            capacity: capacity.max(1),
+        }
+    }
+}
+
+impl Default for HashSetEventIdStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventIdStore for HashSetEventIdStore {
+    fn is_seen(&mut self, id: &str) -> bool {
+        self.ids.contains(id)
+    }
+
+    fn mark_seen(&mut self, id: &str) {
+        if !self.ids.insert(id.to_owned()) {
+            return;
+        }
+        self.order.push_back(id.to_owned());
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+    }
+}
+
+pub struct DeduplicatingHandler<H, S> {
+    handler: H,
+    store: S,
+}
+
+impl<H, S> DeduplicatingHandler<H, S>
+where
+    H: HandleWebhookEvent,
+    S: EventIdStore,
+{
+    pub fn new(handler: H, store: S) -> Self {
+        DeduplicatingHandler { handler, store }
+    }
+}
+
+impl<H, S> HandleWebhookEvent for DeduplicatingHandler<H, S>
+where
+    H: HandleWebhookEvent,
+    S: EventIdStore,
+{
+    fn handle_webhook_event(&mut self, event: &LineEvent) -> Option<Reply> {
+        if is_duplicate(&mut self.store, event) {
+            return None;
+        }
+        let reply = self.handler.handle_webhook_event(event);
+        // ハンドラがパニックした場合は処理済みとせず、再送されたイベントを処理できるようにする。
+        // リプライの送信結果は考慮しない。
+        mark_handled(&mut self.store, event);
+        reply
+    }
+}
+
+// webhookイベントIDを持たないイベントは重複判定できないため、未処理として扱う。
+pub(crate) fn is_duplicate(store: &mut impl EventIdStore, event: &LineEvent) -> bool {
+    match &event.event.common_property().webhook_event_id {
+        Some(id) if store.is_seen(id) => {
+            debug!("処理済みのwebhookイベントを破棄します。イベントID[{}]", id);
+            true
+        }
+        _ => false,
+    }
+}
+
+pub(crate) fn mark_handled(store: &mut impl EventIdStore, event: &LineEvent) {
+    if let Some(id) = &event.event.common_property().webhook_event_id {
+        store.mark_seen(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_marks_ids_seen() {
+        let mut store = HashSetEventIdStore::new();
+        assert!(!store.is_seen("a"));
+        store.mark_seen("a");
+        assert!(store.is_seen("a"));
+    }
+
+    #[test]
+    fn store_evicts_oldest_ids_first() {
+        let mut store = HashSetEventIdStore::with_capacity(2);
+        store.mark_seen("a");
+        store.mark_seen("b");
+        // 既に保持しているIDを再登録しても順序は変わらない。
+        store.mark_seen("a");
+        store.mark_seen("c");
+        assert!(!store.is_seen("a"));
+        assert!(store.is_seen("b"));
+        assert!(store.is_seen("c"));
+        store.mark_seen("d");
+        assert!(!store.is_seen("b"));
+        assert!(store.is_seen("c"));
+        assert!(store.is_seen("d"));
+    }
+}
@@ -1,7 +1,67 @@
-use serde::Deserialize;
-use serde_json::Number;
+#[cfg(feature = "chrono")]
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Number, Value};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+#[cfg(feature = "chrono")]
+use thiserror::Error;
 
-#[derive(Deserialize, Debug)]
+// snake_case_outputフィーチャ有効時はフィールド名をスネークケースで出力する。受信時は常にキャメルケースで解釈する。
+// 新しい型・バリアントで変換の指定漏れが起きないよう、指定はこのマクロに集約する。
+// 列挙型は構造体バリアントのフィールド名に適用する。
+macro_rules! line_fields {
+    ($(#[$attr:meta])* pub struct $name:ident $body:tt) => {
+        $(#[$attr])*
+        #[cfg_attr(not(feature = "snake_case_output"), serde(rename_all = "camelCase"))]
+        #[cfg_attr(
+            feature = "snake_case_output",
+            serde(rename_all(serialize = "snake_case", deserialize = "camelCase"))
+        )]
+        pub struct $name $body
+    };
+    ($(#[$attr:meta])* pub enum $name:ident $body:tt) => {
+        $(#[$attr])*
+        #[cfg_attr(not(feature = "snake_case_output"), serde(rename_all_fields = "camelCase"))]
+        #[cfg_attr(
+            feature = "snake_case_output",
+            serde(rename_all_fields(serialize = "snake_case", deserialize = "camelCase"))
+        )]
+        pub enum $name $body
+    };
+}
+
+// 署名検証済みのwebhookリクエストから得られたイベント群。
+#[derive(Debug)]
+pub struct WebhookEventBatch {
+    pub destination: String,
+    pub events: Vec<WebhookEvent>,
+    // 型付きの解析に失敗したイベントの生のJSON。
+    pub raw_events: Vec<Value>,
+}
+
+// 障害時に古いイベントが再送されることがあるため、タイムスタンプで絞り込めるようにする。
+// タイムスタンプを解釈できないイベントはいずれの絞り込みにも含めない。
+impl WebhookEventBatch {
+    pub fn events_after(&self, since: SystemTime) -> impl Iterator<Item = &WebhookEvent> {
+        self.events.iter().filter(move |event| {
+            event
+                .common_property()
+                .timestamp_as_system_time()
+                .is_some_and(|timestamp| timestamp >= since)
+        })
+    }
+
+    pub fn events_before(&self, until: SystemTime) -> impl Iterator<Item = &WebhookEvent> {
+        self.events.iter().filter(move |event| {
+            event
+                .common_property()
+                .timestamp_as_system_time()
+                .is_some_and(|timestamp| timestamp < until)
+        })
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 #[serde(tag = "type")]
 pub enum WebhookEvent {
@@ -59,139 +119,303 @@ pub enum WebhookEvent {
 }
 
 impl WebhookEvent {
-    pub(crate) fn get_reply_token(self) -> Option<String> {
+    pub fn common_property(&self) -> &EventCommonProperty {
         match self {
-            WebhookEvent::Message {
-                property,
-                message: _,
-            } => property.reply_token,
-            WebhookEvent::Follow { property } => property.reply_token,
-            WebhookEvent::Unfollow { property } => property.reply_token,
-            WebhookEvent::Join { property } => property.reply_token,
-            WebhookEvent::Leave { property } => property.reply_token,
-            WebhookEvent::MemberJoined {
-                property,
-                joined: _,
-            } => property.reply_token,
-            WebhookEvent::MemberLeft { property, left: _ } => property.reply_token,
-            WebhookEvent::Postback {
-                property,
-                postback: _,
-            } => property.reply_token,
-            WebhookEvent::Beacon {
-                property,
-                beacon: _,
-            } => property.reply_token,
-            WebhookEvent::AccountLink { property, link: _ } => property.reply_token,
-            WebhookEvent::Things {
-                property,
-                things: _,
-            } => property.reply_token,
+            WebhookEvent::Message { property, .. } => property,
+            WebhookEvent::Follow { property } => property,
+            WebhookEvent::Unfollow { property } => property,
+            WebhookEvent::Join { property } => property,
+            WebhookEvent::Leave { property } => property,
+            WebhookEvent::MemberJoined { property, .. } => property,
+            WebhookEvent::MemberLeft { property, .. } => property,
+            WebhookEvent::Postback { property, .. } => property,
+            WebhookEvent::Beacon { property, .. } => property,
+            WebhookEvent::AccountLink { property, .. } => property,
+            WebhookEvent::Things { property, .. } => property,
         }
     }
+
+    pub fn is_from_user(&self) -> bool {
+        matches!(self.common_property().source, Source::User { .. })
+    }
+
+    pub fn is_from_group(&self) -> bool {
+        matches!(self.common_property().source, Source::Group { .. })
+    }
+
+    pub fn is_from_room(&self) -> bool {
+        matches!(self.common_property().source, Source::Room { .. })
+    }
+
+    // serdeのタグ名（LINEのイベントタイプ文字列）と一致させること。
+    pub fn event_type_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::Message { .. } => "message",
+            WebhookEvent::Follow { .. } => "follow",
+            WebhookEvent::Unfollow { .. } => "unfollow",
+            WebhookEvent::Join { .. } => "join",
+            WebhookEvent::Leave { .. } => "leave",
+            WebhookEvent::MemberJoined { .. } => "memberJoined",
+            WebhookEvent::MemberLeft { .. } => "memberLeft",
+            WebhookEvent::Postback { .. } => "postback",
+            WebhookEvent::Beacon { .. } => "beacon",
+            WebhookEvent::AccountLink { .. } => "accountLink",
+            WebhookEvent::Things { .. } => "things",
+        }
+    }
+
+    pub(crate) fn get_reply_token(&self) -> Option<String> {
+        self.common_property().reply_token.clone()
+    }
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-pub struct EventCommonProperty {
-    pub(crate) reply_token: Option<String>,
-    pub timestamp: Number,
-    pub source: Source,
+line_fields! {
+    #[derive(Deserialize, Serialize, Debug)]
+    pub struct EventCommonProperty {
+        pub webhook_event_id: Option<String>,
+        pub(crate) reply_token: Option<String>,
+        pub timestamp: Number,
+        pub source: Source,
+    }
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-#[serde(tag = "type")]
-pub enum Source {
-    #[serde(rename_all = "camelCase")]
-    User { user_id: String },
-    #[serde(rename_all = "camelCase")]
-    Group {
-        group_id: String,
-        user_id: Option<String>,
-    },
-    #[serde(rename_all = "camelCase")]
-    Room {
-        room_id: String,
-        user_id: Option<String>,
-    },
+impl EventCommonProperty {
+    pub fn source_user_id(&self) -> Option<&str> {
+        self.source.user_id()
+    }
+
+    pub fn reply_token(&self) -> Option<&str> {
+        self.reply_token.as_deref()
+    }
+
+    pub fn timestamp_as_system_time(&self) -> Option<SystemTime> {
+        self.timestamp
+            .as_u64()
+            .map(|millis| UNIX_EPOCH + Duration::from_millis(millis))
+    }
+
+    // タイムスタンプはミリ秒単位のUNIX時間。整数として解釈できない場合はNoneを返す。
+    #[cfg(feature = "chrono")]
+    pub fn timestamp_as_datetime(&self) -> Option<DateTime<Utc>> {
+        self.timestamp
+            .as_i64()
+            .and_then(|millis| Utc.timestamp_millis_opt(millis).single())
+    }
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-#[serde(tag = "type")]
-pub enum WebhookMessage {
-    Text {
-        id: String,
-        text: String,
-    },
-    #[serde(rename_all = "camelCase")]
-    Image {
-        id: String,
-        content_provider: ContentProvider,
-    },
+line_fields! {
+    #[derive(Deserialize, Serialize, Debug)]
     #[serde(rename_all = "camelCase")]
-    Video {
-        id: String,
-        duration: Number,
-        content_provider: ContentProvider,
-    },
-    #[serde(rename_all = "camelCase")]
-    Audio {
-        id: String,
-        duration: Number,
-        content_provider: ContentProvider,
-    },
-    #[serde(rename_all = "camelCase")]
-    File {
-        id: String,
-        file_name: String,
-        file_size: Number,
-    },
-    Location {
-        id: String,
-        title: String,
-        address: String,
-        latitude: Number,
-        longitude: Number,
-    },
+    #[serde(tag = "type")]
+    pub enum Source {
+        User { user_id: String },
+        Group {
+            group_id: String,
+            user_id: Option<String>,
+        },
+        Room {
+            room_id: String,
+            user_id: Option<String>,
+        },
+    }
+}
+
+impl Source {
+    pub fn user_id(&self) -> Option<&str> {
+        match self {
+            Source::User { user_id } => Some(user_id),
+            Source::Group { user_id, .. } => user_id.as_deref(),
+            Source::Room { user_id, .. } => user_id.as_deref(),
+        }
+    }
+
+    // グループ・トークルームでは送信者のユーザーIDが取得できない場合がある。
+    pub fn is_known_user(&self) -> bool {
+        self.user_id().is_some()
+    }
+
+    // トーク単位の状態管理のキーとして使用できる識別子を返す。
+    pub fn chat_id(&self) -> &str {
+        match self {
+            Source::User { user_id } => user_id,
+            Source::Group { group_id, .. } => group_id,
+            Source::Room { room_id, .. } => room_id,
+        }
+    }
+
+    pub fn to_send_target(&self) -> Option<SendTarget> {
+        let target = match self {
+            Source::User { user_id } => SendTarget::User(user_id.clone()),
+            Source::Group { group_id, .. } => SendTarget::Group(group_id.clone()),
+            Source::Room { room_id, .. } => SendTarget::Room(room_id.clone()),
+        };
+        Some(target)
+    }
+}
+
+// プッシュメッセージ等の送信先。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SendTarget {
+    User(String),
+    Group(String),
+    Room(String),
+}
+
+impl SendTarget {
+    pub fn id(&self) -> &str {
+        match self {
+            SendTarget::User(id) => id,
+            SendTarget::Group(id) => id,
+            SendTarget::Room(id) => id,
+        }
+    }
+}
+
+line_fields! {
+    #[derive(Deserialize, Serialize, Debug)]
     #[serde(rename_all = "camelCase")]
-    Sticker {
-        id: String,
-        package_id: String,
-        sticker_id: String,
-    },
+    #[serde(tag = "type")]
+    pub enum WebhookMessage {
+        Text {
+            id: String,
+            text: String,
+        },
+        Image {
+            id: String,
+            content_provider: ContentProvider,
+        },
+        Video {
+            id: String,
+            duration: Number,
+            content_provider: ContentProvider,
+        },
+        Audio {
+            id: String,
+            duration: Number,
+            content_provider: ContentProvider,
+        },
+        File {
+            id: String,
+            file_name: String,
+            file_size: Number,
+        },
+        Location {
+            id: String,
+            title: String,
+            address: String,
+            latitude: Number,
+            longitude: Number,
+        },
+        Sticker {
+            id: String,
+            package_id: String,
+            sticker_id: String,
+        },
+    }
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-#[serde(tag = "type")]
-pub enum ContentProvider {
-    Line,
+impl WebhookMessage {
+    pub fn is_media(&self) -> bool {
+        matches!(
+            self,
+            WebhookMessage::Image { .. }
+                | WebhookMessage::Video { .. }
+                | WebhookMessage::Audio { .. }
+                | WebhookMessage::File { .. }
+        )
+    }
+
+    pub fn text(&self) -> Option<&str> {
+        match self {
+            WebhookMessage::Text { text, .. } => Some(text),
+            _ => None,
+        }
+    }
+
+    // 緯度・経度を数値として解釈できない場合もNoneを返す。
+    pub fn location_coords(&self) -> Option<(f64, f64)> {
+        match self {
+            WebhookMessage::Location {
+                latitude,
+                longitude,
+                ..
+            } => Some((latitude.as_f64()?, longitude.as_f64()?)),
+            _ => None,
+        }
+    }
+
+    // ファイルサイズを整数として解釈できない場合もNoneを返す。
+    pub fn file_info(&self) -> Option<(&str, u64)> {
+        match self {
+            WebhookMessage::File {
+                file_name,
+                file_size,
+                ..
+            } => file_size.as_u64().map(|size| (file_name.as_str(), size)),
+            _ => None,
+        }
+    }
+}
+
+line_fields! {
+    #[derive(Deserialize, Serialize, Debug)]
     #[serde(rename_all = "camelCase")]
-    External {
-        original_content_url: String,
-        preview_image_url: Option<String>,
-    },
+    #[serde(tag = "type")]
+    pub enum ContentProvider {
+        Line,
+        External {
+            original_content_url: String,
+            preview_image_url: Option<String>,
+        },
+    }
 }
 
-#[derive(Deserialize, Debug)]
+impl ContentProvider {
+    pub fn is_external(&self) -> bool {
+        matches!(self, ContentProvider::External { .. })
+    }
+
+    // LINEのサーバーから取得できるのはLINE上にあるコンテンツのみ。
+    pub fn is_line_hosted(&self) -> bool {
+        matches!(self, ContentProvider::Line)
+    }
+
+    pub fn original_url(&self) -> Option<&str> {
+        match self {
+            ContentProvider::Line => None,
+            ContentProvider::External {
+                original_content_url,
+                ..
+            } => Some(original_content_url),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
 pub struct Joined {
     pub members: Vec<Source>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 pub struct Left {
     pub members: Vec<Source>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 pub struct Postback {
     pub data: String,
     pub params: Params,
 }
 
-#[derive(Deserialize, Debug)]
+impl Postback {
+    // JSONとして解析する前の簡易判定。先頭の文字のみを確認する。
+    pub fn data_is_json(&self) -> bool {
+        let data = self.data.trim_start();
+        data.starts_with('{') || data.starts_with('[')
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub enum Params {
     Date(String),
@@ -199,7 +423,74 @@ pub enum Params {
     Datetime(String),
 }
 
-#[derive(Deserialize, Debug)]
+impl Params {
+    pub fn as_date_str(&self) -> Option<&str> {
+        match self {
+            Params::Date(date) => Some(date),
+            _ => None,
+        }
+    }
+
+    pub fn as_time_str(&self) -> Option<&str> {
+        match self {
+            Params::Time(time) => Some(time),
+            _ => None,
+        }
+    }
+
+    pub fn as_datetime_str(&self) -> Option<&str> {
+        match self {
+            Params::Datetime(datetime) => Some(datetime),
+            _ => None,
+        }
+    }
+}
+
+// LINEの日時選択アクションの書式（YYYY-MM-DD、HH:mm、YYYY-MM-DDTHH:mm）で解析する。
+#[cfg(feature = "chrono")]
+impl Params {
+    pub fn as_naive_date(&self) -> Option<Result<NaiveDate, ParamsParseError>> {
+        self.as_date_str().map(|date| {
+            NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|error| ParamsParseError::Parse {
+                value: date.to_owned(),
+                error,
+            })
+        })
+    }
+
+    pub fn as_naive_time(&self) -> Option<Result<NaiveTime, ParamsParseError>> {
+        self.as_time_str().map(|time| {
+            NaiveTime::parse_from_str(time, "%H:%M").map_err(|error| ParamsParseError::Parse {
+                value: time.to_owned(),
+                error,
+            })
+        })
+    }
+
+    pub fn as_naive_datetime(&self) -> Option<Result<NaiveDateTime, ParamsParseError>> {
+        self.as_datetime_str().map(|datetime| {
+            NaiveDateTime::parse_from_str(datetime, "%Y-%m-%dT%H:%M").map_err(|error| {
+                ParamsParseError::Parse {
+                    value: datetime.to_owned(),
+                    error,
+                }
+            })
+        })
+    }
+}
+
+#[cfg(feature = "chrono")]
+#[derive(Debug, Error)]
+pub enum ParamsParseError {
+    #[error("Parse error: value = {}", .value)]
+    Parse {
+        value: String,
+        #[source]
+        error: chrono::ParseError,
+    },
+}
+
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 #[serde(tag = "type")]
 pub enum Beacon {
@@ -217,34 +508,61 @@ pub enum Beacon {
     },
 }
 
-#[derive(Deserialize, Debug)]
+impl Beacon {
+    // dmは16進数文字列のため、バイト列に復号して返す。復号できない場合はNoneを返す。
+    pub fn device_message_bytes(&self) -> Option<Vec<u8>> {
+        let property = match self {
+            Beacon::Enter { property }
+            | Beacon::Leave { property }
+            | Beacon::Banner { property } => property,
+        };
+        let dm = property.dm.as_ref()?.as_bytes();
+        // from_str_radixは先頭の「+」を許容するため、16進数の文字のみで構成されていることを先に確認する。
+        if !dm.len().is_multiple_of(2) || !dm.iter().all(u8::is_ascii_hexdigit) {
+            return None;
+        }
+        dm.chunks(2)
+            .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
 pub struct BeaconCommonProperty {
     pub hwid: String,
     pub dm: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(tag = "result")]
+#[derive(Deserialize, Serialize, Debug)]
 pub struct Link {
     pub result: LinkResult,
     pub nonce: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub enum LinkResult {
     Ok,
     Failed,
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-#[serde(tag = "type")]
-pub enum Things {
-    #[serde(rename_all = "camelCase")]
-    Link { device_id: String },
+line_fields! {
+    #[derive(Deserialize, Serialize, Debug)]
     #[serde(rename_all = "camelCase")]
-    Unlink { device_id: String },
+    #[serde(tag = "type")]
+    pub enum Things {
+        Link { device_id: String },
+        Unlink { device_id: String },
+    }
+}
+
+impl Things {
+    pub fn device_id(&self) -> &str {
+        match self {
+            Things::Link { device_id } => device_id,
+            Things::Unlink { device_id } => device_id,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -268,4 +586,109 @@ mod tests {
             panic!("Not a text message!")
         }
     }
+
+    #[test]
+    fn deserialize_location_event() {
+        let json_str = r#"
+              {
+                "replyToken": "nHuyWiB7yP5Zw52FIkcQobQuGDXCTA",
+                "type": "message",
+                "mode": "active",
+                "timestamp": 1462629479859,
+                "source": {
+                  "type": "user",
+                  "userId": "U4af4980629..."
+                },
+                "webhookEventId": "01FZ74A0TDDPYRVKNK77XKC3ZR",
+                "message": {
+                  "id": "325708",
+                  "type": "location",
+                  "title": "my location",
+                  "address": "1-6-1 Yotsuya, Shinjuku-ku, Tokyo, 160-0004, Japan",
+                  "latitude": 35.687574,
+                  "longitude": 139.72922
+                }
+              }
+        "#;
+        let event: WebhookEvent = serde_json::from_str(json_str).unwrap();
+        if let WebhookEvent::Message { message, .. } = &event {
+            assert_eq!(message.location_coords(), Some((35.687574, 139.72922)));
+        } else {
+            panic!("Not a message event!")
+        }
+    }
+
+    fn beacon(dm: Option<&str>) -> Beacon {
+        Beacon::Enter {
+            property: BeaconCommonProperty {
+                hwid: "d41d8cd98f".to_owned(),
+                dm: dm.map(str::to_owned),
+            },
+        }
+    }
+
+    #[test]
+    fn device_message_bytes_valid() {
+        assert_eq!(
+            beacon(Some("1a2BfF")).device_message_bytes(),
+            Some(vec![0x1a, 0x2b, 0xff])
+        );
+        assert_eq!(beacon(None).device_message_bytes(), None);
+    }
+
+    #[test]
+    fn device_message_bytes_odd_length() {
+        assert_eq!(beacon(Some("1a2")).device_message_bytes(), None);
+    }
+
+    #[test]
+    fn device_message_bytes_non_hex() {
+        assert_eq!(beacon(Some("1g")).device_message_bytes(), None);
+        assert_eq!(beacon(Some("+f")).device_message_bytes(), None);
+    }
+
+    #[cfg(feature = "snake_case_output")]
+    #[test]
+    fn snake_case_output() {
+        let json_str = r#"
+              {
+                "replyToken": "nHuyWiB7yP5Zw52FIkcQobQuGDXCTA",
+                "type": "message",
+                "timestamp": 1462629479859,
+                "source": {
+                  "type": "group",
+                  "groupId": "Ca56f94637c...",
+                  "userId": "U4af4980629..."
+                },
+                "webhookEventId": "01FZ74A0TDDPYRVKNK77XKC3ZR",
+                "message": {
+                  "id": "325708",
+                  "type": "sticker",
+                  "packageId": "1",
+                  "stickerId": "1"
+                }
+              }
+        "#;
+        let event: WebhookEvent = serde_json::from_str(json_str).unwrap();
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({
+                "type": "message",
+                "webhook_event_id": "01FZ74A0TDDPYRVKNK77XKC3ZR",
+                "reply_token": "nHuyWiB7yP5Zw52FIkcQobQuGDXCTA",
+                "timestamp": 1462629479859u64,
+                "source": {
+                    "type": "group",
+                    "group_id": "Ca56f94637c...",
+                    "user_id": "U4af4980629..."
+                },
+                "message": {
+                    "type": "sticker",
+                    "id": "325708",
+                    "package_id": "1",
+                    "sticker_id": "1"
+                }
+            })
+        );
+    }
 }
@@ -0,0 +1,57 @@
+use crate::channel::{HandleWebhookEvent, LineEvent};
+use crate::event::WebhookEvent;
+use crate::reply::Reply;
+
+pub trait HandleWebhookEventExt: HandleWebhookEvent + Sized {
+    // 条件を満たすイベントのみ内側のハンドラに渡す。
+    fn filter<F>(self, f: F) -> FilteredHandler<Self, F>
+    where
+        F: FnMut(&LineEvent) -> bool,
+    {
+        FilteredHandler {
+            handler: self,
+            filter: f,
+        }
+    }
+
+    fn only_messages(self) -> MessageFilteredHandler<Self> {
+        MessageFilteredHandler { handler: self }
+    }
+}
+
+impl<H> HandleWebhookEventExt for H where H: HandleWebhookEvent {}
+
+pub struct FilteredHandler<H, F> {
+    handler: H,
+    filter: F,
+}
+
+impl<H, F> HandleWebhookEvent for FilteredHandler<H, F>
+where
+    H: HandleWebhookEvent,
+    F: FnMut(&LineEvent) -> bool,
+{
+    fn handle_webhook_event(&mut self, event: &LineEvent) -> Option<Reply> {
+        if (self.filter)(event) {
+            self.handler.handle_webhook_event(event)
+        } else {
+            None
+        }
+    }
+}
+
+pub struct MessageFilteredHandler<H> {
+    handler: H,
+}
+
+impl<H> HandleWebhookEvent for MessageFilteredHandler<H>
+where
+    H: HandleWebhookEvent,
+{
+    fn handle_webhook_event(&mut self, event: &LineEvent) -> Option<Reply> {
+        match event.event {
+            WebhookEvent::Message { .. } => self.handler.handle_webhook_event(event),
+            _ => None,
+        }
+    }
+}
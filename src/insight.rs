@@ -0,0 +1,69 @@
+use crate::bot::{self, BotClient, BotResult};
+use log::debug;
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct InsightMessageDelivery {
+    pub status: String,
+    pub broadcast: Option<u64>,
+    pub targeting: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct InsightFollowers {
+    pub status: String,
+    pub followers: Option<u64>,
+    pub targeted_reaches: Option<u64>,
+    pub blocks: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AggregateMessageStats {
+    pub clicks: Vec<AggregationEntry>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AggregationEntry {
+    pub seq: u32,
+    pub url: String,
+    pub click: Option<u64>,
+    pub unique_click: Option<u64>,
+    pub unique_click_of_request: Option<u64>,
+}
+
+// 日付はyyyyMMdd形式（UTC+9）で指定する。
+pub(crate) fn get_message_delivery(
+    bot: &BotClient,
+    date: &str,
+) -> BotResult<InsightMessageDelivery> {
+    debug!("メッセージ配信数の取得リクエストを行います。日付[{}]", date);
+    let mut res = bot::send(
+        bot.get("/v2/bot/insight/message/delivery")
+            .query(&[("date", date)]),
+    )?;
+    Ok(res.json()?)
+}
+
+pub(crate) fn get_followers(bot: &BotClient, date: &str) -> BotResult<InsightFollowers> {
+    debug!("友だち数の取得リクエストを行います。日付[{}]", date);
+    let mut res = bot::send(
+        bot.get("/v2/bot/insight/followers")
+            .query(&[("date", date)]),
+    )?;
+    Ok(res.json()?)
+}
+
+pub(crate) fn get_aggregate_message_statistics(
+    bot: &BotClient,
+    request_id: &str,
+) -> BotResult<AggregateMessageStats> {
+    debug!("メッセージ集計情報の取得リクエストを行います。リクエストID[{}]", request_id);
+    let mut res = bot::send(
+        bot.get("/v2/bot/message/aggregation/info")
+            .query(&[("requestId", request_id)]),
+    )?;
+    Ok(res.json()?)
+}
@@ -1,9 +1,43 @@
+mod action;
 mod api;
+pub mod audience;
+mod bot;
 mod channel;
+mod config;
+mod dedup;
 pub mod event;
-mod oauth;
+mod filter;
+pub mod insight;
+pub mod member;
+pub mod message;
+mod middleware;
+pub mod oauth;
+mod pool;
 pub mod reply;
 mod request;
+pub mod rich_menu;
+#[cfg(test)]
+mod test_util;
+pub mod user;
+pub mod webhook;
 
+pub use action::{
+    Action, DatetimePickerAction, DatetimePickerMode, MessageAction, PostbackAction, UriAction,
+};
 pub use api::{MessagingApi, MessagingError, MessagingResult};
-pub use channel::{Channel, HandleWebhookEvent};
+pub use bot::BotError;
+pub use channel::{
+    handler_fn, Channel, HandleWebhookEvent, InvalidUserIdError, LineEvent, UserId,
+};
+pub use config::{ChannelConfig, MessagingApiConfig};
+pub use dedup::{DeduplicatingHandler, EventIdStore, HashSetEventIdStore};
+pub use filter::{FilteredHandler, HandleWebhookEventExt, MessageFilteredHandler};
+pub use middleware::{
+    DeduplicationMiddleware, EventMiddleware, LoggingMiddleware, MiddlewareChain, MiddlewareResult,
+    RateLimitMiddleware,
+};
+pub use pool::ChannelPool;
+pub use reply::{respond, text_reply};
+pub use request::{RequestBody, RequestBodyError};
+
+pub const X_LINE_SIGNATURE_HEADER: &str = "X-Line-Signature";
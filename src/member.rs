@@ -0,0 +1,51 @@
+use crate::bot::{self, BotClient, BotResult};
+use log::debug;
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MemberIdsPage {
+    pub member_ids: Vec<String>,
+    // 続きのメンバーが存在する場合のみ返される継続トークン。
+    pub next: Option<String>,
+}
+
+pub(crate) fn get_group_member_ids(
+    bot: &BotClient,
+    group_id: &str,
+    continuation_token: Option<&str>,
+) -> BotResult<MemberIdsPage> {
+    debug!(
+        "グループメンバーのユーザーID取得リクエストを行います。グループID[{}]",
+        group_id
+    );
+    let url = format!("/v2/bot/group/{}/members/ids", group_id);
+    get_member_ids(bot, &url, continuation_token)
+}
+
+// トークルームは新規に作成できないが、既存のトークルームのイベントには対応する必要がある。
+pub(crate) fn get_room_member_ids(
+    bot: &BotClient,
+    room_id: &str,
+    continuation_token: Option<&str>,
+) -> BotResult<MemberIdsPage> {
+    debug!(
+        "トークルームメンバーのユーザーID取得リクエストを行います。トークルームID[{}]",
+        room_id
+    );
+    let url = format!("/v2/bot/room/{}/members/ids", room_id);
+    get_member_ids(bot, &url, continuation_token)
+}
+
+fn get_member_ids(
+    bot: &BotClient,
+    url: &str,
+    continuation_token: Option<&str>,
+) -> BotResult<MemberIdsPage> {
+    let mut req = bot.get(url);
+    if let Some(start) = continuation_token {
+        req = req.query(&[("start", start)]);
+    }
+    let mut res = bot::send(req)?;
+    Ok(res.json()?)
+}
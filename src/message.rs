@@ -0,0 +1,159 @@
+use crate::bot::{self, BotClient, BotError, BotResult};
+use crate::reply::ReplyMessage;
+use bytes::Bytes;
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+#[serde(tag = "type")]
+pub enum MessageQuota {
+    None,
+    Limited { value: u64 },
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageQuotaConsumption {
+    pub total_usage: u64,
+}
+
+pub(crate) fn get_content_preview(bot: &BotClient, message_id: &str) -> BotResult<Bytes> {
+    debug!(
+        "メッセージコンテンツのプレビュー取得リクエストを行います。メッセージID[{}]",
+        message_id
+    );
+    let url = format!("/v2/bot/message/{}/content/preview", message_id);
+    let mut res = bot::send(bot.get_data(&url))?;
+    let mut buf = Vec::new();
+    res.copy_to(&mut buf)?;
+    Ok(Bytes::from(buf))
+}
+
+pub(crate) fn validate_reply(bot: &BotClient, messages: &[ReplyMessage]) -> BotResult<()> {
+    debug!(
+        "リプライメッセージの検証リクエストを行います。メッセージ[{:?}]",
+        messages
+    );
+    bot::send(
+        bot.post("/v2/bot/message/validate/reply")
+            .json(&MessagesRequestBody { messages }),
+    )?;
+    Ok(())
+}
+
+#[derive(Serialize, Debug)]
+struct MessagesRequestBody<'a> {
+    messages: &'a [ReplyMessage],
+}
+
+pub(crate) fn broadcast(
+    bot: &BotClient,
+    messages: &[ReplyMessage],
+    retry_key: &str,
+) -> BotResult<()> {
+    debug!(
+        "ブロードキャストメッセージの送信リクエストを行います。メッセージ[{:?}]",
+        messages
+    );
+    bot::send_with_retry_key(
+        bot.post("/v2/bot/message/broadcast")
+            .json(&MessagesRequestBody { messages }),
+        retry_key,
+    )
+}
+
+pub(crate) fn push(
+    bot: &BotClient,
+    to: &str,
+    messages: &[ReplyMessage],
+    retry_key: &str,
+) -> BotResult<()> {
+    debug!(
+        "プッシュメッセージの送信リクエストを行います。宛先[{}], メッセージ[{:?}]",
+        to, messages
+    );
+    bot::send_with_retry_key(
+        bot.post("/v2/bot/message/push")
+            .json(&PushRequestBody { to, messages }),
+        retry_key,
+    )
+}
+
+#[derive(Serialize, Debug)]
+struct PushRequestBody<'a> {
+    to: &'a str,
+    messages: &'a [ReplyMessage],
+}
+
+pub(crate) fn get_quota(bot: &BotClient) -> BotResult<MessageQuota> {
+    debug!("メッセージ上限数の取得リクエストを行います。");
+    let mut res = bot::send(bot.get("/v2/bot/message/quota"))?;
+    Ok(res.json()?)
+}
+
+pub(crate) fn get_quota_consumption(bot: &BotClient) -> BotResult<MessageQuotaConsumption> {
+    debug!("メッセージ送信数の取得リクエストを行います。");
+    let mut res = bot::send(bot.get("/v2/bot/message/quota/consumption"))?;
+    Ok(res.json()?)
+}
+
+// 表示秒数は5秒単位で最大60秒まで指定できる。
+pub(crate) fn start_loading_animation(
+    bot: &BotClient,
+    chat_id: &str,
+    loading_seconds: u8,
+) -> BotResult<()> {
+    if loading_seconds == 0 || loading_seconds > 60 || !loading_seconds.is_multiple_of(5) {
+        return Err(BotError::InvalidArgument {
+            message: format!(
+                "ローディングの表示秒数は5秒単位で60秒以下を指定してください。秒数[{}]",
+                loading_seconds
+            ),
+        });
+    }
+    debug!(
+        "ローディングアニメーションの表示リクエストを行います。チャットID[{}], 秒数[{}]",
+        chat_id, loading_seconds
+    );
+    bot::send(
+        bot.post("/v2/bot/chat/loading/start")
+            .json(&LoadingRequestBody {
+                chat_id,
+                loading_seconds,
+            }),
+    )?;
+    Ok(())
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct LoadingRequestBody<'a> {
+    chat_id: &'a str,
+    loading_seconds: u8,
+}
+
+pub(crate) fn mark_as_read(bot: &BotClient, chat_id: &str) -> BotResult<()> {
+    debug!(
+        "メッセージの既読化リクエストを行います。チャットID[{}]",
+        chat_id
+    );
+    bot::send(
+        bot.post("/v2/bot/message/markAsRead")
+            .json(&MarkAsReadRequestBody {
+                chat: Chat { user_id: chat_id },
+            }),
+    )?;
+    Ok(())
+}
+
+#[derive(Serialize, Debug)]
+struct MarkAsReadRequestBody<'a> {
+    chat: Chat<'a>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Chat<'a> {
+    user_id: &'a str,
+}
@@ -0,0 +1,195 @@
+use crate::channel::{HandleWebhookEvent, LineEvent};
+use crate::dedup::{self, EventIdStore};
+use crate::reply::Reply;
+use log::debug;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MiddlewareResult {
+    Continue,
+    Stop,
+}
+
+pub trait EventMiddleware {
+    fn process(&mut self, event: &LineEvent) -> MiddlewareResult;
+}
+
+// 登録順にミドルウェアを適用し、全てがContinueを返した場合のみハンドラを呼び出す。
+pub struct MiddlewareChain<H> {
+    middlewares: Vec<Box<dyn EventMiddleware + Send + 'static>>,
+    handler: H,
+}
+
+impl<H> MiddlewareChain<H>
+where
+    H: HandleWebhookEvent,
+{
+    pub fn new(handler: H) -> Self {
+        MiddlewareChain {
+            middlewares: Vec::new(),
+            handler,
+        }
+    }
+
+    pub fn with(mut self, middleware: impl EventMiddleware + Send + 'static) -> Self {
+        self.middlewares.push(Box::new(middleware));
+        self
+    }
+}
+
+impl<H> HandleWebhookEvent for MiddlewareChain<H>
+where
+    H: HandleWebhookEvent,
+{
+    fn handle_webhook_event(&mut self, event: &LineEvent) -> Option<Reply> {
+        for middleware in &mut self.middlewares {
+            if middleware.process(event) == MiddlewareResult::Stop {
+                return None;
+            }
+        }
+        self.handler.handle_webhook_event(event)
+    }
+}
+
+#[derive(Default)]
+pub struct LoggingMiddleware;
+
+impl EventMiddleware for LoggingMiddleware {
+    fn process(&mut self, event: &LineEvent) -> MiddlewareResult {
+        debug!(
+            "webhookイベントを受信しました。チャンネルID[{}], イベントタイプ[{}], イベント[{:?}]",
+            event.channel_id,
+            event.event.event_type_str(),
+            event.event
+        );
+        MiddlewareResult::Continue
+    }
+}
+
+pub struct DeduplicationMiddleware<S> {
+    store: S,
+}
+
+impl<S> DeduplicationMiddleware<S>
+where
+    S: EventIdStore,
+{
+    pub fn new(store: S) -> Self {
+        DeduplicationMiddleware { store }
+    }
+}
+
+// ミドルウェアはハンドラの結果を知り得ないため、通過した時点で処理済みとする（at-most-once）。
+// ハンドラがパニックしたイベントの再送を処理する場合はDeduplicatingHandlerを使用すること。
+impl<S> EventMiddleware for DeduplicationMiddleware<S>
+where
+    S: EventIdStore,
+{
+    fn process(&mut self, event: &LineEvent) -> MiddlewareResult {
+        if dedup::is_duplicate(&mut self.store, event) {
+            return MiddlewareResult::Stop;
+        }
+        dedup::mark_handled(&mut self.store, event);
+        MiddlewareResult::Continue
+    }
+}
+
+// トーク毎に、一定時間内に処理するイベント数を制限する。
+pub struct RateLimitMiddleware {
+    max_events: usize,
+    window: Duration,
+    counters: HashMap<String, (Instant, usize)>,
+    pruned_at: Instant,
+}
+
+impl RateLimitMiddleware {
+    pub fn new(max_events: usize, window: Duration) -> Self {
+        RateLimitMiddleware {
+            max_events,
+            window,
+            counters: HashMap::new(),
+            pruned_at: Instant::now(),
+        }
+    }
+
+    // トーク数に応じてカウンタが増え続けないよう、期間の過ぎたカウンタを期間毎に破棄する。
+    fn prune(&mut self, now: Instant) {
+        if now.duration_since(self.pruned_at) < self.window {
+            return;
+        }
+        let window = self.window;
+        self.counters
+            .retain(|_, (started_at, _)| now.duration_since(*started_at) < window);
+        self.pruned_at = now;
+    }
+}
+
+impl RateLimitMiddleware {
+    fn check(&mut self, chat_id: &str, now: Instant) -> MiddlewareResult {
+        self.prune(now);
+        let (started_at, count) = self.counters.entry(chat_id.to_owned()).or_insert((now, 0));
+        if now.duration_since(*started_at) >= self.window {
+            *started_at = now;
+            *count = 0;
+        }
+        if *count >= self.max_events {
+            debug!(
+                "流量制限によりwebhookイベントを破棄します。チャットID[{}]",
+                chat_id
+            );
+            return MiddlewareResult::Stop;
+        }
+        *count += 1;
+        MiddlewareResult::Continue
+    }
+}
+
+impl EventMiddleware for RateLimitMiddleware {
+    fn process(&mut self, event: &LineEvent) -> MiddlewareResult {
+        let chat_id = event.event.common_property().source.chat_id();
+        self.check(chat_id, Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn rate_limit_drops_events_over_limit() {
+        let mut middleware = RateLimitMiddleware::new(2, WINDOW);
+        let now = Instant::now();
+        assert_eq!(middleware.check("U1", now), MiddlewareResult::Continue);
+        assert_eq!(middleware.check("U1", now), MiddlewareResult::Continue);
+        assert_eq!(middleware.check("U1", now), MiddlewareResult::Stop);
+        // トーク毎に計数する。
+        assert_eq!(middleware.check("U2", now), MiddlewareResult::Continue);
+    }
+
+    #[test]
+    fn rate_limit_resets_after_window() {
+        let mut middleware = RateLimitMiddleware::new(1, WINDOW);
+        let now = Instant::now();
+        assert_eq!(middleware.check("U1", now), MiddlewareResult::Continue);
+        assert_eq!(middleware.check("U1", now), MiddlewareResult::Stop);
+        assert_eq!(
+            middleware.check("U1", now + WINDOW),
+            MiddlewareResult::Continue
+        );
+    }
+
+    #[test]
+    fn rate_limit_prunes_expired_counters() {
+        let mut middleware = RateLimitMiddleware::new(1, WINDOW);
+        let now = Instant::now();
+        middleware.check("U1", now);
+        middleware.check("U2", now);
+        assert_eq!(middleware.counters.len(), 2);
+        middleware.check("U3", now + WINDOW);
+        assert_eq!(middleware.counters.len(), 1);
+        assert!(middleware.counters.contains_key("U3"));
+    }
+}
@@ -1,35 +1,59 @@
-use failure::Fail;
 use log::{debug, error};
 use reqwest::header;
+use reqwest::Client;
 use serde::Deserialize;
 use serde_json::Number;
+use std::time::{Duration, Instant};
+use thiserror::Error;
 
-pub(crate) fn issue_access_token(channel_id: usize, channel_secret: &str) -> OAuthResult<String> {
+pub(crate) fn issue_access_token(
+    client: &Client,
+    base_url: &str,
+    channel_id: usize,
+    channel_secret: &str,
+) -> OAuthResult<(String, Instant)> {
     debug!("チャンネルアクセストークン発行リクエストを行います。");
-    let client = reqwest::Client::new();
     let mut res = client
-        .post("https://api.line.me/v2/oauth/accessToken")
+        .post(&format!("{}/v2/oauth/accessToken", base_url))
         .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
         .form(&[
             ("grant_type", "client_credentials"),
             ("client_id", &channel_id.to_string()),
             ("client_secret", channel_secret),
         ])
-        .send()
-        .unwrap();
+        .send()?;
     debug!("チャンネルアクセストークン発行リクエストを送信しました。");
     if res.status() == 200 {
         debug!("チャンネルアクセストークン発行リクエストに成功しました。");
-        let res_body: ResponseBody = res.json().unwrap();
-        Ok(res_body.access_token)
+        let res_body: ResponseBody = res.json()?;
+        let expires_at = res_body.expires_at();
+        Ok((res_body.access_token, expires_at))
     } else if res.status() == 400 {
-        let e_res_body: ErrorResponseBody = res.json().unwrap();
+        let e_res_body: ErrorResponseBody = res.json()?;
         error!("チャンネルアクセストークン発行リクエストエラーレスポンスを受信しました。ステータス[{}], エラーレスポンス[{:?}]"
                , res.status(), e_res_body);
+        // 発行済みトークンの一覧はAPIから取得できないため、最古のトークンを自動で失効させることはできない。
+        if e_res_body.error == "too_many_request" {
+            return Err(OAuthError::TokenLimitExceeded);
+        }
         Err(OAuthError::ErrorResponse {
             message: e_res_body.error,
             description: e_res_body.error_description,
         })
+    } else if res.status() == 401 {
+        error!("チャンネルIDまたはチャンネルシークレットが不正です。ステータス[{}]", res.status());
+        Err(OAuthError::InvalidChannelCredentials)
+    } else if res.status() == 429 {
+        let retry_after = res
+            .headers()
+            .get(header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok());
+        error!(
+            "チャンネルアクセストークン発行リクエストが流量制限を受けました。Retry-After[{:?}]",
+            retry_after
+        );
+        Err(OAuthError::RateLimited { retry_after })
     } else {
         error!(
             "チャンネルアクセストークン発行リクエストに失敗しました。ステータス[{}]",
@@ -41,22 +65,185 @@ pub(crate) fn issue_access_token(channel_id: usize, channel_secret: &str) -> OAu
     }
 }
 
+pub(crate) fn revoke_access_token(
+    client: &Client,
+    base_url: &str,
+    access_token: &str,
+) -> OAuthResult<()> {
+    debug!("チャンネルアクセストークン失効リクエストを行います。");
+    let mut res = client
+        .post(&format!("{}/v2/oauth/revoke", base_url))
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .form(&[("access_token", access_token)])
+        .send()?;
+    if res.status() == 200 {
+        debug!("チャンネルアクセストークン失効リクエストに成功しました。");
+        Ok(())
+    } else if res.status() == 400 {
+        let e_res_body: ErrorResponseBody = res.json()?;
+        error!("チャンネルアクセストークン失効リクエストエラーレスポンスを受信しました。ステータス[{}], エラーレスポンス[{:?}]"
+               , res.status(), e_res_body);
+        Err(OAuthError::ErrorResponse {
+            message: e_res_body.error,
+            description: e_res_body.error_description,
+        })
+    } else {
+        error!(
+            "チャンネルアクセストークン失効リクエストに失敗しました。ステータス[{}]",
+            res.status()
+        );
+        Err(OAuthError::UnexpectedStatusResponse {
+            status: u16::from(res.status()),
+        })
+    }
+}
+
+// チャンネルの秘密鍵で署名したJWTアサーションを用いてv2.1のトークンを発行する。
+#[cfg(feature = "v2_1_auth")]
+pub(crate) fn issue_access_token_v2_1(
+    client: &Client,
+    base_url: &str,
+    channel_id: &str,
+    private_key_pem: &str,
+    kid: &str,
+) -> OAuthResult<String> {
+    use jsonwebtoken::{Algorithm, EncodingKey, Header};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("システム時刻がUNIXエポックより前です。")
+        .as_secs();
+    let claims = AssertionClaims {
+        iss: channel_id,
+        sub: channel_id,
+        aud: "https://api.line.me/",
+        // アサーション自体の有効期限は最大30分。
+        exp: now + 60 * 30,
+        // 発行するトークンの有効期限は最大30日。
+        token_exp: 60 * 60 * 24 * 30,
+    };
+    let mut jwt_header = Header::new(Algorithm::RS256);
+    jwt_header.kid = Some(kid.to_owned());
+    let key = EncodingKey::from_rsa_pem(private_key_pem.as_bytes())?;
+    let assertion = jsonwebtoken::encode(&jwt_header, &claims, &key)?;
+
+    debug!("チャンネルアクセストークン(v2.1)発行リクエストを行います。");
+    let mut res = client
+        .post(&format!("{}/oauth2/v2.1/token", base_url))
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .form(&[
+            ("grant_type", "client_credentials"),
+            (
+                "client_assertion_type",
+                "urn:ietf:params:oauth:client-assertion-type:jwt-bearer",
+            ),
+            ("client_assertion", &assertion),
+        ])
+        .send()?;
+    if res.status() == 200 {
+        debug!("チャンネルアクセストークン(v2.1)発行リクエストに成功しました。");
+        let res_body: ResponseBody = res.json()?;
+        Ok(res_body.access_token)
+    } else if res.status() == 400 {
+        let e_res_body: ErrorResponseBody = res.json()?;
+        error!("チャンネルアクセストークン(v2.1)発行リクエストエラーレスポンスを受信しました。ステータス[{}], エラーレスポンス[{:?}]"
+               , res.status(), e_res_body);
+        Err(OAuthError::ErrorResponse {
+            message: e_res_body.error,
+            description: e_res_body.error_description,
+        })
+    } else {
+        error!(
+            "チャンネルアクセストークン(v2.1)発行リクエストに失敗しました。ステータス[{}]",
+            res.status()
+        );
+        Err(OAuthError::UnexpectedStatusResponse {
+            status: u16::from(res.status()),
+        })
+    }
+}
+
+#[cfg(feature = "v2_1_auth")]
+#[derive(serde::Serialize, Debug)]
+struct AssertionClaims<'a> {
+    iss: &'a str,
+    sub: &'a str,
+    aud: &'a str,
+    exp: u64,
+    token_exp: u64,
+}
+
+pub(crate) fn verify_access_token(
+    client: &Client,
+    base_url: &str,
+    access_token: &str,
+) -> OAuthResult<VerifyTokenResponse> {
+    debug!("チャンネルアクセストークン検証リクエストを行います。");
+    let mut res = client
+        .post(&format!("{}/v2/oauth/verify", base_url))
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .form(&[("access_token", access_token)])
+        .send()?;
+    if res.status() == 200 {
+        debug!("チャンネルアクセストークン検証リクエストに成功しました。");
+        Ok(res.json()?)
+    } else if res.status() == 400 {
+        let e_res_body: ErrorResponseBody = res.json()?;
+        error!("チャンネルアクセストークン検証リクエストエラーレスポンスを受信しました。ステータス[{}], エラーレスポンス[{:?}]"
+               , res.status(), e_res_body);
+        Err(OAuthError::ErrorResponse {
+            message: e_res_body.error,
+            description: e_res_body.error_description,
+        })
+    } else {
+        error!(
+            "チャンネルアクセストークン検証リクエストに失敗しました。ステータス[{}]",
+            res.status()
+        );
+        Err(OAuthError::UnexpectedStatusResponse {
+            status: u16::from(res.status()),
+        })
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct VerifyTokenResponse {
+    pub client_id: String,
+    pub expires_in: u64,
+    pub scope: String,
+}
+
 type OAuthResult<T> = Result<T, OAuthError>;
 
-#[derive(Debug, Fail)]
+#[derive(Debug, Error)]
 pub enum OAuthError {
-    #[fail(
-        display = "Error response: {}, description: {:?}",
-        message, description
-    )]
+    #[error("Error response: {}, description: {:?}", .message, .description)]
     ErrorResponse {
         message: String,
         description: Option<String>,
     },
-    #[fail(display = "Request error: {}", error)]
-    Reqwest { error: reqwest::Error },
-    #[fail(display = "Unexpected status response: status = {}", status)]
+    #[error("Request error")]
+    Reqwest {
+        #[source]
+        error: reqwest::Error,
+    },
+    #[error("Unexpected status response: status = {}", .status)]
     UnexpectedStatusResponse { status: u16 },
+    #[error("Invalid channel credentials")]
+    InvalidChannelCredentials,
+    #[error("Rate limited: retry after {:?} seconds", .retry_after)]
+    RateLimited { retry_after: Option<u64> },
+    #[error(
+        "Token limit exceeded: the channel already has 30 active access tokens, revoke unused tokens and retry"
+    )]
+    TokenLimitExceeded,
+    #[cfg(feature = "v2_1_auth")]
+    #[error("JWT error")]
+    Jwt {
+        #[source]
+        error: jsonwebtoken::errors::Error,
+    },
 }
 
 impl From<reqwest::Error> for OAuthError {
@@ -65,6 +252,13 @@ impl From<reqwest::Error> for OAuthError {
     }
 }
 
+#[cfg(feature = "v2_1_auth")]
+impl From<jsonwebtoken::errors::Error> for OAuthError {
+    fn from(error: jsonwebtoken::errors::Error) -> Self {
+        OAuthError::Jwt { error }
+    }
+}
+
 #[derive(Deserialize, Debug)]
 struct ResponseBody {
     access_token: String,
@@ -72,8 +266,164 @@ struct ResponseBody {
     token_type: String,
 }
 
+impl ResponseBody {
+    // 有効期間(秒)を解釈できない場合は、即時に期限切れとして扱う。
+    fn expires_at(&self) -> Instant {
+        let expires_in = self.expires_in.as_u64().unwrap_or(0);
+        Instant::now() + Duration::from_secs(expires_in)
+    }
+}
+
 #[derive(Deserialize, Debug)]
 struct ErrorResponseBody {
     error: String,
     error_description: Option<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{blocking, unreachable_base_url};
+    use serde_json::json;
+    use wiremock::matchers::{body_string_contains, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn issue(server: &MockServer) -> OAuthResult<(String, Instant)> {
+        issue_to(server.uri()).await
+    }
+
+    async fn issue_to(base_url: String) -> OAuthResult<(String, Instant)> {
+        blocking(move || issue_access_token(&Client::new(), &base_url, 1234567890, "secret")).await
+    }
+
+    #[tokio::test]
+    async fn issue_access_token_success() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v2/oauth/accessToken"))
+            .and(body_string_contains("grant_type=client_credentials"))
+            .and(body_string_contains("client_id=1234567890"))
+            .and(body_string_contains("client_secret=secret"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "access_token": "W1TeHCgfH2Liwa",
+                "expires_in": 2592000,
+                "token_type": "Bearer"
+            })))
+            .mount(&server)
+            .await;
+        let (token, expires_at) = issue(&server).await.unwrap();
+        assert_eq!(token, "W1TeHCgfH2Liwa");
+        assert!(expires_at > Instant::now() + Duration::from_secs(2591000));
+    }
+
+    #[tokio::test]
+    async fn issue_access_token_error_response() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v2/oauth/accessToken"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                "error": "invalid_request",
+                "error_description": "some parameters are missing or invalid"
+            })))
+            .mount(&server)
+            .await;
+        match issue(&server).await {
+            Err(OAuthError::ErrorResponse {
+                message,
+                description,
+            }) => {
+                assert_eq!(message, "invalid_request");
+                assert_eq!(
+                    description.as_deref(),
+                    Some("some parameters are missing or invalid")
+                );
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn issue_access_token_limit_exceeded() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v2/oauth/accessToken"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                "error": "too_many_request"
+            })))
+            .mount(&server)
+            .await;
+        match issue(&server).await {
+            Err(OAuthError::TokenLimitExceeded) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn issue_access_token_rate_limited() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v2/oauth/accessToken"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "30"))
+            .mount(&server)
+            .await;
+        match issue(&server).await {
+            Err(OAuthError::RateLimited { retry_after }) => assert_eq!(retry_after, Some(30)),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn issue_access_token_connection_error() {
+        match issue_to(unreachable_base_url()).await {
+            Err(OAuthError::Reqwest { error }) => assert!(error.status().is_none()),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    async fn verify(server: &MockServer) -> OAuthResult<VerifyTokenResponse> {
+        let base_url = server.uri();
+        blocking(move || verify_access_token(&Client::new(), &base_url, "W1TeHCgfH2Liwa")).await
+    }
+
+    #[tokio::test]
+    async fn verify_access_token_success() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v2/oauth/verify"))
+            .and(body_string_contains("access_token=W1TeHCgfH2Liwa"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "scope": "P",
+                "client_id": "1350031035",
+                "expires_in": 3138007490u64
+            })))
+            .mount(&server)
+            .await;
+        let res = verify(&server).await.unwrap();
+        assert_eq!(res.client_id, "1350031035");
+        assert_eq!(res.expires_in, 3138007490);
+        assert_eq!(res.scope, "P");
+    }
+
+    #[tokio::test]
+    async fn verify_access_token_error_response() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v2/oauth/verify"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                "error": "invalid_request",
+                "error_description": "access token expired"
+            })))
+            .mount(&server)
+            .await;
+        match verify(&server).await {
+            Err(OAuthError::ErrorResponse {
+                message,
+                description,
+            }) => {
+                assert_eq!(message, "invalid_request");
+                assert_eq!(description.as_deref(), Some("access token expired"));
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,154 @@
+#[cfg(feature = "tokio")]
+use crate::api::MessagingError;
+use crate::api::MessagingResult;
+use crate::channel::{AccessToken, Channel, Handler, UserId};
+use crate::config::{build_client, ChannelConfig, MessagingApiConfig};
+use dashmap::DashMap;
+use log::debug;
+use reqwest::Client;
+use std::sync::{Arc, Mutex, RwLock};
+
+// tokioフィーチャ有効時は、.awaitを跨いでロックを保持できるtokioのMutexを使用する。
+// std::sync::MutexGuardは!Sendのため、.awaitを跨いで保持するとtokio::spawnでコンパイルエラーとなる。
+#[cfg(not(feature = "tokio"))]
+pub(crate) type HandlerMutex = std::sync::Mutex<Handler>;
+#[cfg(feature = "tokio")]
+pub(crate) type HandlerMutex = tokio::sync::Mutex<Handler>;
+
+#[cfg(not(feature = "tokio"))]
+pub(crate) fn lock_handler(
+    handler: &HandlerMutex,
+) -> MessagingResult<std::sync::MutexGuard<'_, Handler>> {
+    Ok(handler.lock().unwrap())
+}
+
+// ランタイム上でblocking_lockを呼び出すとパニックするため、ランタイムの種類に応じてロック方法を切り替える。
+// マルチスレッドランタイムではワーカースレッドを退避してから待機する。
+// シングルスレッドランタイムでは待機するとデッドロックし得るため、ロックを取得できなければエラーとする。
+#[cfg(feature = "tokio")]
+pub(crate) fn lock_handler(
+    handler: &HandlerMutex,
+) -> MessagingResult<tokio::sync::MutexGuard<'_, Handler>> {
+    use tokio::runtime::{Handle, RuntimeFlavor};
+
+    let handle = match Handle::try_current() {
+        Ok(handle) => handle,
+        Err(_) => return Ok(handler.blocking_lock()),
+    };
+    if let Ok(guard) = handler.try_lock() {
+        return Ok(guard);
+    }
+    match handle.runtime_flavor() {
+        RuntimeFlavor::MultiThread => Ok(tokio::task::block_in_place(|| handler.blocking_lock())),
+        _ => Err(MessagingError::ChannelBusy {
+            message: "チャンネルが処理中のためロックを取得できません。非同期コードからは*_asyncメソッドを使用してください。"
+                .to_owned(),
+        }),
+    }
+}
+
+// 登録済みのチャンネルの状態。
+// ハンドラの呼び出し中にもハンドラ内からAPIを呼び出せるよう、シークレット・トークン・ハンドラは個別にロックする。
+// シークレットとトークンの両方をロックする場合は、トークン、シークレットの順にロックすること。
+pub(crate) struct ChannelEntry {
+    pub(crate) id: usize,
+    pub(crate) user_id: UserId,
+    pub(crate) secret: RwLock<String>,
+    pub(crate) token: Mutex<AccessToken>,
+    pub(crate) client: Client,
+    pub(crate) config: ChannelConfig,
+    pub(crate) handler: HandlerMutex,
+}
+
+impl ChannelEntry {
+    // 個別設定の無いチャンネルは単一のクライアントを共有し、コネクションプールを使い回す。
+    fn new(channel: Channel, client: &Client, defaults: &ChannelConfig) -> Self {
+        ChannelEntry {
+            id: channel.id,
+            user_id: channel.user_id,
+            secret: RwLock::new(channel.secret),
+            token: Mutex::new(channel.token),
+            client: channel.client.unwrap_or_else(|| client.clone()),
+            config: channel.config.unwrap_or_else(|| defaults.clone()),
+            handler: HandlerMutex::new(channel.handler),
+        }
+    }
+
+    // リクエスト中や再試行の待機中にロックを保持しないよう、トークンは複製して返す。
+    // トークンの発行中は同一チャンネルの他のリクエストを待機させ、重複して発行しないようにする。
+    pub(crate) fn access_token(&self) -> MessagingResult<String> {
+        let mut token = self.token.lock().unwrap();
+        let secret = self.secret.read().unwrap();
+        Ok(token
+            .ensure(&self.client, &self.config.base_url, self.id, &secret)?
+            .to_owned())
+    }
+
+    // 旧シークレットで発行したアクセストークンは破棄する。
+    pub(crate) fn update_secret(&self, new_secret: String) {
+        let mut token = self.token.lock().unwrap();
+        *self.secret.write().unwrap() = new_secret;
+        *token = AccessToken::default();
+    }
+}
+
+pub struct ChannelPool {
+    channels: DashMap<UserId, Arc<ChannelEntry>>,
+    client: Client,
+    config: MessagingApiConfig,
+}
+
+impl ChannelPool {
+    pub fn new() -> Self {
+        ChannelPool::with_config(MessagingApiConfig::default())
+    }
+
+    pub fn with_config(config: MessagingApiConfig) -> Self {
+        ChannelPool {
+            channels: DashMap::new(),
+            client: build_client(&config.channel_defaults),
+            config,
+        }
+    }
+
+    pub fn add_channel(&self, channel: Channel) {
+        debug!("チャンネルを登録します。ユーザーID[{}]", channel.user_id);
+        let entry = ChannelEntry::new(channel, &self.client, &self.config.channel_defaults);
+        self.channels.insert(entry.user_id.clone(), Arc::new(entry));
+    }
+
+    pub fn remove_channel(&self, user_id: &str) -> bool {
+        debug!("チャンネルを登録解除します。ユーザーID[{}]", user_id);
+        self.channels.remove(user_id).is_some()
+    }
+
+    pub fn contains(&self, user_id: &str) -> bool {
+        self.channels.contains_key(user_id)
+    }
+
+    // マップのロックを早期に解放するため、チャンネルはArcを複製して返す。
+    pub(crate) fn get(&self, user_id: &str) -> Option<Arc<ChannelEntry>> {
+        self.channels.get(user_id).map(|entry| Arc::clone(entry.value()))
+    }
+
+    pub(crate) fn channels(&self) -> Vec<Arc<ChannelEntry>> {
+        self.channels
+            .iter()
+            .map(|entry| Arc::clone(entry.value()))
+            .collect()
+    }
+
+    pub(crate) fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub(crate) fn config(&self) -> &MessagingApiConfig {
+        &self.config
+    }
+}
+
+impl Default for ChannelPool {
+    fn default() -> Self {
+        ChannelPool::new()
+    }
+}
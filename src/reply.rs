@@ -1,65 +1,361 @@
-use failure::Fail;
+use crate::action::{Action, PostbackAction};
 use log::{debug, error};
 use reqwest::header;
 use reqwest::Client;
-use serde::Serialize;
+use serde::ser::{self, SerializeStruct};
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::Value;
+use std::num::NonZeroU64;
+use thiserror::Error;
 
-#[derive(Serialize, Debug)]
-#[serde(rename_all = "camelCase")]
+#[derive(Debug)]
 pub struct Reply {
     pub(crate) reply_token: String,
     pub messages: Vec<ReplyMessage>,
     notification_disabled: bool,
+    sender: Option<Sender>,
 }
 
 impl Reply {
-    pub fn new(messages: Vec<ReplyMessage>, notification_disabled: bool) -> Self {
+    pub fn with_notification_setting(
+        messages: Vec<ReplyMessage>,
+        notification_disabled: bool,
+    ) -> Self {
         Reply {
             reply_token: "".to_owned(),
             messages,
             notification_disabled,
+            sender: None,
         }
     }
+
+    // 通知を有効にしたリプライを生成する。
+    pub fn notification_disabled_default(messages: Vec<ReplyMessage>) -> Self {
+        Reply::with_notification_setting(messages, false)
+    }
+
+    // チャンネルのディスパッチを経由せずにrespondを呼び出す場合に使用する。
+    pub fn new_with_token(
+        reply_token: impl Into<String>,
+        messages: Vec<ReplyMessage>,
+        notification_disabled: bool,
+    ) -> Self {
+        Reply::with_notification_setting(messages, notification_disabled)
+            .with_reply_token(reply_token)
+    }
+
+    pub fn with_reply_token(mut self, reply_token: impl Into<String>) -> Self {
+        self.reply_token = reply_token.into();
+        self
+    }
+
+    pub fn with_sender(mut self, sender: Sender) -> Self {
+        self.sender = Some(sender);
+        self
+    }
+}
+
+// respondを直接呼び出す場合も含め、送信経路によらず既定の送信者が適用されるよう、シリアライズ時に適用する。
+impl Serialize for Reply {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Reply", 3)?;
+        state.serialize_field("replyToken", &self.reply_token)?;
+        match &self.sender {
+            Some(sender) => {
+                let messages = self
+                    .messages
+                    .iter()
+                    .map(|message| with_default_sender(message, sender))
+                    .collect::<Result<Vec<Value>, _>>()
+                    .map_err(<S::Error as ser::Error>::custom)?;
+                state.serialize_field("messages", &messages)?;
+            }
+            None => state.serialize_field("messages", &self.messages)?,
+        }
+        state.serialize_field("notificationDisabled", &self.notification_disabled)?;
+        state.end()
+    }
+}
+
+// リプライ単位の送信者を、送信者が未指定のメッセージにのみ適用する。
+fn with_default_sender(message: &ReplyMessage, sender: &Sender) -> serde_json::Result<Value> {
+    let mut value = serde_json::to_value(message)?;
+    if let Value::Object(map) = &mut value {
+        if !map.contains_key("sender") {
+            map.insert("sender".to_owned(), serde_json::to_value(sender)?);
+        }
+    }
+    Ok(value)
 }
 
 #[derive(Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 #[serde(tag = "type")]
 pub enum ReplyMessage {
-    Text { text: String },
+    Text {
+        text: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        emojis: Option<Vec<EmojiSubstitution>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sender: Option<Sender>,
+    },
+    // 再生時間が0の音声メッセージはAPIエラーとなるため、型で0を除外する。
+    #[serde(rename_all = "camelCase")]
+    Audio {
+        original_content_url: String,
+        duration: DurationMs,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sender: Option<Sender>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Imagemap {
+        base_url: String,
+        alt_text: String,
+        base_size: ImagemapSize,
+        actions: Vec<ImagemapAction>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sender: Option<Sender>,
+    },
+    // テンプレートはバリアントの中で特に大きいため、ボックス化してReplyMessage全体のサイズを抑える。
+    #[serde(rename_all = "camelCase")]
+    Template {
+        alt_text: String,
+        template: Box<Template>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sender: Option<Sender>,
+    },
+}
+
+impl ReplyMessage {
+    // CRLFは2文字として表示されるため、LFに変換してから末尾の空白を除去する。
+    pub fn text_normalized(text: impl Into<String>) -> ReplyMessage {
+        let text = text.into().replace("\r\n", "\n");
+        ReplyMessage::from(text.trim_end())
+    }
+
+    // はい／いいえの選択肢をポストバックアクションとして持つ確認テンプレートを生成する。
+    pub fn confirm_template(
+        alt_text: impl Into<String>,
+        question: impl Into<String>,
+        yes_label: impl Into<String>,
+        no_label: impl Into<String>,
+        yes_data: impl Into<String>,
+        no_data: impl Into<String>,
+    ) -> ReplyMessage {
+        ReplyMessage::Template {
+            alt_text: alt_text.into(),
+            template: Box::new(Template::Confirm {
+                text: question.into(),
+                actions: vec![
+                    Action::Postback(PostbackAction {
+                        label: yes_label.into(),
+                        data: yes_data.into(),
+                        display_text: None,
+                    }),
+                    Action::Postback(PostbackAction {
+                        label: no_label.into(),
+                        data: no_data.into(),
+                        display_text: None,
+                    }),
+                ],
+            }),
+            sender: None,
+        }
+    }
+}
+
+impl From<String> for ReplyMessage {
+    fn from(text: String) -> Self {
+        ReplyMessage::Text {
+            text,
+            emojis: None,
+            sender: None,
+        }
+    }
+}
+
+impl From<&str> for ReplyMessage {
+    fn from(text: &str) -> Self {
+        ReplyMessage::from(text.to_owned())
+    }
+}
+
+// ミリ秒単位の再生時間。
+pub type DurationMs = NonZeroU64;
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EmojiSubstitution {
+    pub index: u32,
+    pub product_id: String,
+    pub emoji_id: String,
 }
 
-pub(crate) fn respond(access_token: &str, reply: &Reply) -> ReplyResult<()> {
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+#[serde(tag = "type")]
+pub enum Template {
+    #[serde(rename_all = "camelCase")]
+    Buttons {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        thumbnail_image_url: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        image_aspect_ratio: Option<ImageAspectRatio>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        image_size: Option<ImageSize>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        image_background_color: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        title: Option<String>,
+        text: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        default_action: Option<Box<Action>>,
+        actions: Vec<Action>,
+    },
+    Confirm {
+        text: String,
+        actions: Vec<Action>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Carousel {
+        columns: Vec<CarouselColumn>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        image_aspect_ratio: Option<ImageAspectRatio>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        image_size: Option<ImageSize>,
+    },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CarouselColumn {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail_image_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    pub text: String,
+    pub actions: Vec<Action>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum ImageAspectRatio {
+    Rectangle,
+    Square,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum ImageSize {
+    Cover,
+    Contain,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ImagemapSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+#[serde(tag = "type")]
+pub enum ImagemapAction {
+    Uri(ImagemapUriAction),
+    Message(ImagemapMessageAction),
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ImagemapUriAction {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    pub link_uri: String,
+    pub area: ImagemapArea,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ImagemapMessageAction {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    pub text: String,
+    pub area: ImagemapArea,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ImagemapArea {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Sender {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon_url: Option<String>,
+}
+
+pub fn text_reply(text: impl Into<String>) -> Reply {
+    Reply::notification_disabled_default(vec![ReplyMessage::from(text.into())])
+}
+
+pub fn respond(
+    client: &Client,
+    base_url: &str,
+    access_token: &str,
+    reply: &Reply,
+) -> ReplyResult<()> {
     debug!(
         "リプライのリクエストを行います。アクセストークン[{}], リプライ[{:?}]",
         access_token, reply
     );
-    let client = Client::new();
-    let res = client
-        .post("https://api.line.me/v2/bot/message/reply")
+    let mut res = client
+        .post(&format!("{}/v2/bot/message/reply", base_url))
         // .headers(headers)
         .header(header::CONTENT_TYPE, "application/json")
         .header(header::AUTHORIZATION, format!("Bearer {}", access_token))
         .json(reply)
-        .send()
-        .expect("リプライのリクエストでエラー発生");
+        .send()?;
     if res.status() == 200 {
-        debug!("リプライのリクエストに成功しました。")
+        debug!("リプライのリクエストに成功しました。");
+        Ok(())
     } else {
+        let e_res_body = ErrorResponseBody::parse(res.text()?);
         error!(
-            "リプライのリクエストに失敗しました。ステータス[{}]",
-            res.status()
-        )
+            "リプライのリクエストに失敗しました。ステータス[{}], エラーレスポンス[{:?}]",
+            res.status(),
+            e_res_body
+        );
+        Err(ReplyError::ApiError {
+            status: u16::from(res.status()),
+            message: e_res_body.message,
+            details: e_res_body.details,
+        })
     }
-    Ok(())
 }
 
 type ReplyResult<T> = Result<T, ReplyError>;
 
-#[derive(Debug, Fail)]
+#[derive(Debug, Error)]
 pub enum ReplyError {
-    #[fail(display = "Request error: {}", error)]
-    Reqwest { error: reqwest::Error },
+    #[error("Request error")]
+    Reqwest {
+        #[source]
+        error: reqwest::Error,
+    },
+    #[error(
+        "API error: status = {}, message = {}, details = {:?}",
+        .status, .message, .details
+    )]
+    ApiError {
+        status: u16,
+        message: String,
+        details: Vec<LineErrorDetail>,
+    },
 }
 
 impl From<reqwest::Error> for ReplyError {
@@ -67,3 +363,369 @@ impl From<reqwest::Error> for ReplyError {
         ReplyError::Reqwest { error }
     }
 }
+
+#[derive(Deserialize, Debug)]
+pub struct LineErrorDetail {
+    pub message: String,
+    pub property: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct ErrorResponseBody {
+    pub(crate) message: String,
+    #[serde(default)]
+    pub(crate) details: Vec<LineErrorDetail>,
+}
+
+impl ErrorResponseBody {
+    // プロキシ等が返すJSONでないエラーレスポンスは、本文をそのままメッセージとして扱う。
+    pub(crate) fn parse(body: String) -> Self {
+        match serde_json::from_str(&body) {
+            Ok(e_res_body) => e_res_body,
+            Err(_) => ErrorResponseBody {
+                message: body,
+                details: Vec::new(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::{DatetimePickerAction, DatetimePickerMode, MessageAction, UriAction};
+    use crate::test_util::{blocking, unreachable_base_url};
+    use serde_json::json;
+    use wiremock::matchers::{body_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn reply() -> Reply {
+        let mut reply = text_reply("hello");
+        reply.reply_token = "nHuyWiB7yP5Zw52FIkcQobQuGDXCTA".to_owned();
+        reply
+    }
+
+    async fn respond_to(base_url: String) -> ReplyResult<()> {
+        blocking(move || respond(&Client::new(), &base_url, "W1TeHCgfH2Liwa", &reply())).await
+    }
+
+    #[test]
+    fn serialize_text_reply() {
+        let mut reply = text_reply("hello");
+        reply.reply_token = "nHuyWiB7yP5Zw52FIkcQobQuGDXCTA".to_owned();
+        let json_str = serde_json::to_string(&reply).unwrap();
+        assert_eq!(
+            json_str,
+            r#"{"replyToken":"nHuyWiB7yP5Zw52FIkcQobQuGDXCTA","messages":[{"type":"text","text":"hello"}],"notificationDisabled":false}"#
+        );
+    }
+
+    #[test]
+    fn serialize_reply_with_default_sender() {
+        let mut reply = text_reply("hello").with_sender(Sender {
+            name: Some("Bot".to_owned()),
+            icon_url: None,
+        });
+        reply.reply_token = "nHuyWiB7yP5Zw52FIkcQobQuGDXCTA".to_owned();
+        assert_eq!(
+            serde_json::to_value(&reply).unwrap(),
+            json!({
+                "replyToken": "nHuyWiB7yP5Zw52FIkcQobQuGDXCTA",
+                "messages": [{"type": "text", "text": "hello", "sender": {"name": "Bot"}}],
+                "notificationDisabled": false
+            })
+        );
+    }
+
+    #[test]
+    fn serialize_reply_keeps_message_sender() {
+        let message = ReplyMessage::Text {
+            text: "hello".to_owned(),
+            emojis: None,
+            sender: Some(Sender {
+                name: Some("Cony".to_owned()),
+                icon_url: None,
+            }),
+        };
+        let reply = Reply::new_with_token("nHuyWiB7yP5Zw52FIkcQobQuGDXCTA", vec![message], false)
+            .with_sender(Sender {
+                name: Some("Bot".to_owned()),
+                icon_url: None,
+            });
+        assert_eq!(
+            serde_json::to_value(&reply).unwrap(),
+            json!({
+                "replyToken": "nHuyWiB7yP5Zw52FIkcQobQuGDXCTA",
+                "messages": [{"type": "text", "text": "hello", "sender": {"name": "Cony"}}],
+                "notificationDisabled": false
+            })
+        );
+    }
+
+    #[test]
+    fn serialize_buttons_template() {
+        let message = ReplyMessage::Template {
+            alt_text: "menu".to_owned(),
+            template: Box::new(Template::Buttons {
+                thumbnail_image_url: Some("https://example.com/bot/images/image.jpg".to_owned()),
+                image_aspect_ratio: Some(ImageAspectRatio::Rectangle),
+                image_size: Some(ImageSize::Cover),
+                image_background_color: None,
+                title: Some("Menu".to_owned()),
+                text: "Please select".to_owned(),
+                default_action: Some(Box::new(Action::Uri(UriAction {
+                    label: "View detail".to_owned(),
+                    uri: "http://example.com/page/123".to_owned(),
+                }))),
+                actions: vec![
+                    Action::Message(MessageAction {
+                        label: "Yes".to_owned(),
+                        text: "yes".to_owned(),
+                    }),
+                    Action::DatetimePicker(DatetimePickerAction {
+                        label: "Select date".to_owned(),
+                        data: "storeId=12345".to_owned(),
+                        mode: DatetimePickerMode::Datetime,
+                        initial: None,
+                        max: None,
+                        min: None,
+                    }),
+                ],
+            }),
+            sender: None,
+        };
+        assert_eq!(
+            serde_json::to_value(&message).unwrap(),
+            json!({
+                "type": "template",
+                "altText": "menu",
+                "template": {
+                    "type": "buttons",
+                    "thumbnailImageUrl": "https://example.com/bot/images/image.jpg",
+                    "imageAspectRatio": "rectangle",
+                    "imageSize": "cover",
+                    "title": "Menu",
+                    "text": "Please select",
+                    "defaultAction": {
+                        "type": "uri",
+                        "label": "View detail",
+                        "uri": "http://example.com/page/123"
+                    },
+                    "actions": [
+                        {"type": "message", "label": "Yes", "text": "yes"},
+                        {
+                            "type": "datetimepicker",
+                            "label": "Select date",
+                            "data": "storeId=12345",
+                            "mode": "datetime"
+                        }
+                    ]
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn serialize_confirm_template() {
+        let message = ReplyMessage::confirm_template(
+            "this is a confirm template",
+            "Are you sure?",
+            "Yes",
+            "No",
+            "action=yes",
+            "action=no",
+        );
+        assert_eq!(
+            serde_json::to_value(&message).unwrap(),
+            json!({
+                "type": "template",
+                "altText": "this is a confirm template",
+                "template": {
+                    "type": "confirm",
+                    "text": "Are you sure?",
+                    "actions": [
+                        {"type": "postback", "label": "Yes", "data": "action=yes"},
+                        {"type": "postback", "label": "No", "data": "action=no"}
+                    ]
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn serialize_carousel_template() {
+        let message = ReplyMessage::Template {
+            alt_text: "this is a carousel template".to_owned(),
+            template: Box::new(Template::Carousel {
+                columns: vec![CarouselColumn {
+                    thumbnail_image_url: None,
+                    title: Some("this is menu".to_owned()),
+                    text: "description".to_owned(),
+                    actions: vec![Action::Postback(PostbackAction {
+                        label: "Buy".to_owned(),
+                        data: "action=buy&itemid=111".to_owned(),
+                        display_text: Some("Buy".to_owned()),
+                    })],
+                }],
+                image_aspect_ratio: Some(ImageAspectRatio::Square),
+                image_size: Some(ImageSize::Contain),
+            }),
+            sender: None,
+        };
+        assert_eq!(
+            serde_json::to_value(&message).unwrap(),
+            json!({
+                "type": "template",
+                "altText": "this is a carousel template",
+                "template": {
+                    "type": "carousel",
+                    "columns": [{
+                        "title": "this is menu",
+                        "text": "description",
+                        "actions": [{
+                            "type": "postback",
+                            "label": "Buy",
+                            "data": "action=buy&itemid=111",
+                            "displayText": "Buy"
+                        }]
+                    }],
+                    "imageAspectRatio": "square",
+                    "imageSize": "contain"
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn serialize_imagemap() {
+        let message = ReplyMessage::Imagemap {
+            base_url: "https://example.com/bot/images/rm001".to_owned(),
+            alt_text: "This is an imagemap".to_owned(),
+            base_size: ImagemapSize {
+                width: 1040,
+                height: 1040,
+            },
+            actions: vec![
+                ImagemapAction::Uri(ImagemapUriAction {
+                    label: None,
+                    link_uri: "https://example.com/".to_owned(),
+                    area: ImagemapArea {
+                        x: 0,
+                        y: 0,
+                        width: 520,
+                        height: 1040,
+                    },
+                }),
+                ImagemapAction::Message(ImagemapMessageAction {
+                    label: None,
+                    text: "Hello".to_owned(),
+                    area: ImagemapArea {
+                        x: 520,
+                        y: 0,
+                        width: 520,
+                        height: 1040,
+                    },
+                }),
+            ],
+            sender: None,
+        };
+        assert_eq!(
+            serde_json::to_value(&message).unwrap(),
+            json!({
+                "type": "imagemap",
+                "baseUrl": "https://example.com/bot/images/rm001",
+                "altText": "This is an imagemap",
+                "baseSize": {"width": 1040, "height": 1040},
+                "actions": [
+                    {
+                        "type": "uri",
+                        "linkUri": "https://example.com/",
+                        "area": {"x": 0, "y": 0, "width": 520, "height": 1040}
+                    },
+                    {
+                        "type": "message",
+                        "text": "Hello",
+                        "area": {"x": 520, "y": 0, "width": 520, "height": 1040}
+                    }
+                ]
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn respond_success() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v2/bot/message/reply"))
+            .and(header("Authorization", "Bearer W1TeHCgfH2Liwa"))
+            .and(body_json(json!({
+                "replyToken": "nHuyWiB7yP5Zw52FIkcQobQuGDXCTA",
+                "messages": [{"type": "text", "text": "hello"}],
+                "notificationDisabled": false
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .expect(1)
+            .mount(&server)
+            .await;
+        respond_to(server.uri()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn respond_api_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v2/bot/message/reply"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                "message": "The request body has 1 error(s)",
+                "details": [
+                    {"message": "May not be empty", "property": "messages[0].text"}
+                ]
+            })))
+            .mount(&server)
+            .await;
+        match respond_to(server.uri()).await {
+            Err(ReplyError::ApiError {
+                status,
+                message,
+                details,
+            }) => {
+                assert_eq!(status, 400);
+                assert_eq!(message, "The request body has 1 error(s)");
+                assert_eq!(details.len(), 1);
+                assert_eq!(details[0].message, "May not be empty");
+                assert_eq!(details[0].property.as_deref(), Some("messages[0].text"));
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn respond_network_error() {
+        match respond_to(unreachable_base_url()).await {
+            Err(ReplyError::Reqwest { error }) => assert!(error.status().is_none()),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn respond_non_json_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v2/bot/message/reply"))
+            .respond_with(ResponseTemplate::new(502).set_body_string("Bad Gateway"))
+            .mount(&server)
+            .await;
+        match respond_to(server.uri()).await {
+            Err(ReplyError::ApiError {
+                status,
+                message,
+                details,
+            }) => {
+                assert_eq!(status, 502);
+                assert_eq!(message, "Bad Gateway");
+                assert!(details.is_empty());
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+}
@@ -1,29 +1,198 @@
-use crate::event::WebhookEvent;
-use failure::Fail;
+use crate::event::{WebhookEvent, WebhookEventBatch};
+use log::warn;
+use serde::de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor};
 use serde::Deserialize;
+use serde_json::{Deserializer, Value};
 use std::convert::TryFrom;
+use std::fmt;
+use std::io::Read;
+use thiserror::Error;
 
-#[derive(Deserialize, Debug)]
+#[derive(Debug)]
 pub struct RequestBody {
     pub(crate) destination: String,
     pub(crate) events: Vec<WebhookEvent>,
-    #[serde(default)]
+    // 未知のイベントタイプ等で型付きの解析に失敗したイベント。
+    pub(crate) raw_events: Vec<Value>,
     pub(crate) src: String,
 }
 
+impl RequestBody {
+    // イベントが空のリクエストボディをエラーとする。
+    // LINE Developersコンソールからのwebhook検証リクエストはイベントが空のため注意すること。
+    pub(crate) fn try_from_strict(s: String) -> Result<Self, RequestBodyError> {
+        let body = RequestBody::try_from(s)?;
+        if body.events.is_empty() && body.raw_events.is_empty() {
+            return Err(RequestBodyError::EmptyEvents);
+        }
+        Ok(body)
+    }
+
+    // リクエストボディのevents配列を逐次解析し、イベント毎にon_eventを呼び出す。戻り値は宛先のユーザーID。
+    // 型付きの解析に失敗したイベントは、生のJSONを保持したInvalidEventエラーとして渡す。
+    // serdeはデシリアライザ側から値を渡す方式のため、スレッドやボディ全体のバッファ無しに
+    // 呼び出し側から取り出すイテレータにはできない。そのためコールバックで渡す。
+    // ボディ全体を保持しないため、署名検証済みの入力に対して使用すること。
+    pub fn parse_streaming<F>(reader: impl Read, on_event: F) -> Result<String, RequestBodyError>
+    where
+        F: FnMut(Result<WebhookEvent, RequestBodyError>),
+    {
+        let mut deserializer = Deserializer::from_reader(reader);
+        let destination = StreamingBody { on_event }.deserialize(&mut deserializer)?;
+        deserializer.end()?;
+        Ok(destination)
+    }
+}
+
+struct StreamingBody<F> {
+    on_event: F,
+}
+
+impl<'de, F> DeserializeSeed<'de> for StreamingBody<F>
+where
+    F: FnMut(Result<WebhookEvent, RequestBodyError>),
+{
+    type Value = String;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, F> Visitor<'de> for StreamingBody<F>
+where
+    F: FnMut(Result<WebhookEvent, RequestBodyError>),
+{
+    type Value = String;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a webhook request body")
+    }
+
+    fn visit_map<A>(mut self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut destination = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "destination" => destination = Some(map.next_value()?),
+                "events" => map.next_value_seed(StreamingEvents {
+                    on_event: &mut self.on_event,
+                })?,
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        destination.ok_or_else(|| de::Error::missing_field("destination"))
+    }
+}
+
+struct StreamingEvents<'a, F> {
+    on_event: &'a mut F,
+}
+
+impl<'de, 'a, F> DeserializeSeed<'de> for StreamingEvents<'a, F>
+where
+    F: FnMut(Result<WebhookEvent, RequestBodyError>),
+{
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, 'a, F> Visitor<'de> for StreamingEvents<'a, F>
+where
+    F: FnMut(Result<WebhookEvent, RequestBodyError>),
+{
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of webhook events")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        // 1イベントの解析失敗で後続のイベントを失わないよう、イベント毎に解析する。
+        while let Some(value) = seq.next_element::<Value>()? {
+            let event = WebhookEvent::deserialize(&value)
+                .map_err(|error| RequestBodyError::InvalidEvent { raw: value, error });
+            (self.on_event)(event);
+        }
+        Ok(())
+    }
+}
+
+impl From<RequestBody> for WebhookEventBatch {
+    fn from(body: RequestBody) -> Self {
+        WebhookEventBatch {
+            destination: body.destination,
+            events: body.events,
+            raw_events: body.raw_events,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RawRequestBody {
+    destination: String,
+    events: Vec<Value>,
+}
+
 impl TryFrom<String> for RequestBody {
     type Error = RequestBodyError;
     fn try_from(s: String) -> Result<Self, Self::Error> {
-        let mut body = serde_json::from_str::<RequestBody>(&s)?;
-        body.src = s;
-        Ok(body)
+        let raw = serde_json::from_str::<RawRequestBody>(&s)?;
+        if raw.events.is_empty() {
+            warn!("webhookイベントが空のリクエストボディを受信しました。");
+        }
+        let mut events = Vec::with_capacity(raw.events.len());
+        let mut raw_events = Vec::new();
+        // 1イベントの解析失敗でバッチ全体が失敗しないよう、イベント毎に解析する。
+        for value in raw.events {
+            match WebhookEvent::deserialize(&value) {
+                Ok(event) => events.push(event),
+                Err(e) => {
+                    warn!("webhookイベントを解析できませんでした。エラー[{}], イベント[{}]", e, value);
+                    raw_events.push(value);
+                }
+            }
+        }
+        Ok(RequestBody {
+            destination: raw.destination,
+            events,
+            raw_events,
+            src: s,
+        })
     }
 }
 
-#[derive(Debug, Fail)]
+#[derive(Debug, Error)]
 pub enum RequestBodyError {
-    #[fail(display = "Parse error: {}", error)]
-    Parse { error: serde_json::Error },
+    #[error("Parse error")]
+    Parse {
+        #[source]
+        error: serde_json::Error,
+    },
+    #[error("Empty events")]
+    EmptyEvents,
+    #[error("Invalid event")]
+    InvalidEvent {
+        raw: Value,
+        #[source]
+        error: serde_json::Error,
+    },
 }
 
 impl From<serde_json::Error> for RequestBodyError {
@@ -31,3 +200,69 @@ impl From<serde_json::Error> for RequestBodyError {
         RequestBodyError::Parse { error }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_streaming_webhook_body() {
+        let body = r#"
+            {
+                "destination": "xxxxxxxxxx",
+                "events": [
+                    {
+                        "replyToken": "0f3779fba3b349968c5d07db31eab56f",
+                        "type": "message",
+                        "mode": "active",
+                        "timestamp": 1462629479859,
+                        "source": {
+                            "type": "user",
+                            "userId": "U4af4980629..."
+                        },
+                        "webhookEventId": "01FZ74A0TDDPYRVKNK77XKC3ZR",
+                        "deliveryContext": {
+                            "isRedelivery": false
+                        },
+                        "message": {
+                            "id": "444573844083572737",
+                            "type": "text",
+                            "text": "Hello, world"
+                        }
+                    },
+                    {
+                        "type": "unknown",
+                        "timestamp": 1462629479960
+                    },
+                    {
+                        "replyToken": "8cf9239d56244f4197887e939187e19e",
+                        "type": "follow",
+                        "mode": "active",
+                        "timestamp": 1462629479960,
+                        "source": {
+                            "type": "user",
+                            "userId": "U4af4980629..."
+                        }
+                    }
+                ]
+            }
+        "#;
+        let mut events = Vec::new();
+        let destination =
+            RequestBody::parse_streaming(body.as_bytes(), |event| events.push(event)).unwrap();
+        assert_eq!(destination, "xxxxxxxxxx");
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].as_ref().unwrap().event_type_str(), "message");
+        match &events[1] {
+            Err(RequestBodyError::InvalidEvent { raw, .. }) => assert_eq!(raw["type"], "unknown"),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        assert_eq!(events[2].as_ref().unwrap().event_type_str(), "follow");
+    }
+
+    #[test]
+    fn parse_streaming_without_destination() {
+        let result = RequestBody::parse_streaming(r#"{"events": []}"#.as_bytes(), |_| {});
+        assert!(matches!(result, Err(RequestBodyError::Parse { .. })));
+    }
+}
@@ -0,0 +1,157 @@
+use crate::bot::{self, BotClient, BotError, BotResult};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RichMenuSummary {
+    pub rich_menu_id: String,
+    pub size: RichMenuSize,
+    pub selected: bool,
+    pub areas: Vec<RichMenuArea>,
+    pub name: String,
+    pub chat_bar_text: String,
+}
+
+// ID指定で取得するリッチメニューは一覧の要素と同じ構造を持つ。
+pub type RichMenu = RichMenuSummary;
+
+#[derive(Deserialize, Debug)]
+pub struct RichMenuSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RichMenuArea {
+    pub bounds: RichMenuBounds,
+    pub action: Value,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RichMenuBounds {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+pub(crate) fn get(bot: &BotClient, rich_menu_id: &str) -> BotResult<RichMenu> {
+    debug!(
+        "リッチメニューの取得リクエストを行います。リッチメニューID[{}]",
+        rich_menu_id
+    );
+    let url = format!("/v2/bot/richmenu/{}", rich_menu_id);
+    let mut res = bot::send(bot.get(&url))?;
+    Ok(res.json()?)
+}
+
+pub(crate) fn delete(bot: &BotClient, rich_menu_id: &str) -> BotResult<()> {
+    debug!(
+        "リッチメニューの削除リクエストを行います。リッチメニューID[{}]",
+        rich_menu_id
+    );
+    let url = format!("/v2/bot/richmenu/{}", rich_menu_id);
+    bot::send(bot.delete(&url))?;
+    Ok(())
+}
+
+pub(crate) fn set_default(bot: &BotClient, rich_menu_id: &str) -> BotResult<()> {
+    debug!(
+        "デフォルトリッチメニューの設定リクエストを行います。リッチメニューID[{}]",
+        rich_menu_id
+    );
+    let url = format!("/v2/bot/user/all/richmenu/{}", rich_menu_id);
+    bot::send(bot.post(&url))?;
+    Ok(())
+}
+
+pub(crate) fn cancel_default(bot: &BotClient) -> BotResult<()> {
+    debug!("デフォルトリッチメニューの解除リクエストを行います。");
+    bot::send(bot.delete("/v2/bot/user/all/richmenu"))?;
+    Ok(())
+}
+
+pub(crate) fn get_list(bot: &BotClient) -> BotResult<Vec<RichMenuSummary>> {
+    debug!("リッチメニュー一覧の取得リクエストを行います。");
+    let mut res = bot::send(bot.get("/v2/bot/richmenu/list"))?;
+    let res_body: RichMenuListResponseBody = res.json()?;
+    Ok(res_body.richmenus)
+}
+
+pub(crate) fn copy(bot: &BotClient, rich_menu_id: &str) -> BotResult<String> {
+    debug!(
+        "リッチメニューの複製リクエストを行います。リッチメニューID[{}]",
+        rich_menu_id
+    );
+    let url = format!("/v2/bot/richmenu/{}/copy", rich_menu_id);
+    let mut res = bot::send(bot.post(&url))?;
+    let res_body: RichMenuIdResponseBody = res.json()?;
+    Ok(res_body.rich_menu_id)
+}
+
+// リッチメニューがリンクされていない場合は404が返るため、Noneとして扱う。
+pub(crate) fn get_linked(bot: &BotClient, user_id: &str) -> BotResult<Option<String>> {
+    debug!(
+        "ユーザーにリンクされたリッチメニューの取得リクエストを行います。ユーザーID[{}]",
+        user_id
+    );
+    let url = format!("/v2/bot/user/{}/richmenu", user_id);
+    match bot::send(bot.get(&url)) {
+        Ok(mut res) => {
+            let res_body: RichMenuIdResponseBody = res.json()?;
+            Ok(Some(res_body.rich_menu_id))
+        }
+        Err(BotError::ApiError { status: 404, .. }) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RichMenuIdResponseBody {
+    rich_menu_id: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct RichMenuListResponseBody {
+    richmenus: Vec<RichMenuSummary>,
+}
+
+// 一括リンクの結果はリクエストIDで非同期に確認するため、レスポンスヘッダのリクエストIDを返す。
+pub(crate) fn bulk_link(
+    bot: &BotClient,
+    user_ids: &[String],
+    rich_menu_id: &str,
+) -> BotResult<String> {
+    if user_ids.is_empty() || user_ids.len() > MAX_BULK_LINK_USERS {
+        return Err(BotError::InvalidArgument {
+            message: format!(
+                "一括リンクのユーザーIDは1件以上{}件以下で指定してください。件数[{}]",
+                MAX_BULK_LINK_USERS,
+                user_ids.len()
+            ),
+        });
+    }
+    debug!(
+        "リッチメニューの一括リンクリクエストを行います。リッチメニューID[{}], 件数[{}]",
+        rich_menu_id,
+        user_ids.len()
+    );
+    let req_body = BulkLinkRequestBody {
+        rich_menu_id,
+        user_ids,
+    };
+    let res = bot::send(bot.post("/v2/bot/richmenu/bulk/link").json(&req_body))?;
+    Ok(bot::request_id(&res))
+}
+
+const MAX_BULK_LINK_USERS: usize = 500;
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct BulkLinkRequestBody<'a> {
+    rich_menu_id: &'a str,
+    user_ids: &'a [String],
+}
@@ -0,0 +1,16 @@
+use std::net::TcpListener;
+
+// reqwestの同期クライアントを使用するため、ブロッキング用のスレッドで呼び出す。
+pub(crate) async fn blocking<T, F>(f: F) -> T
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    tokio::task::spawn_blocking(f).await.unwrap()
+}
+
+// 一度確保したポートを解放し、接続を受け付けないアドレスを得る。
+pub(crate) fn unreachable_base_url() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    format!("http://{}", listener.local_addr().unwrap())
+}
@@ -0,0 +1,40 @@
+use crate::bot::{self, BotClient, BotResult};
+use log::debug;
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct UserProfile {
+    pub display_name: String,
+    pub user_id: String,
+    pub language: Option<String>,
+    pub picture_url: Option<String>,
+    pub status_message: Option<String>,
+}
+
+pub(crate) fn get_profile(bot: &BotClient, user_id: &str) -> BotResult<UserProfile> {
+    debug!(
+        "プロフィール取得リクエストを行います。ユーザーID[{}]",
+        user_id
+    );
+    let url = format!("/v2/bot/profile/{}", user_id);
+    let mut res = bot::send(bot.get(&url))?;
+    Ok(res.json()?)
+}
+
+pub(crate) fn issue_link_token(bot: &BotClient, user_id: &str) -> BotResult<String> {
+    debug!(
+        "連携トークンの発行リクエストを行います。ユーザーID[{}]",
+        user_id
+    );
+    let url = format!("/v2/bot/user/{}/linkToken", user_id);
+    let mut res = bot::send(bot.post(&url))?;
+    let res_body: LinkTokenResponseBody = res.json()?;
+    Ok(res_body.link_token)
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct LinkTokenResponseBody {
+    link_token: String,
+}
@@ -0,0 +1,41 @@
+use crate::bot::{self, BotClient, BotResult};
+use log::debug;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookTestResult {
+    pub success: bool,
+    pub timestamp: String,
+    pub status_code: u16,
+    pub reason: Option<String>,
+    pub detail: Option<String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub body: String,
+    pub error_message: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookEndpointInfo {
+    pub webhook_endpoint: String,
+    pub active: bool,
+}
+
+pub(crate) fn get_endpoint(bot: &BotClient) -> BotResult<WebhookEndpointInfo> {
+    debug!("webhookエンドポイント情報の取得リクエストを行います。");
+    let mut res = bot::send(bot.get("/v2/bot/channel/webhook/endpoint"))?;
+    Ok(res.json()?)
+}
+
+pub(crate) fn test_webhook(bot: &BotClient) -> BotResult<WebhookTestResult> {
+    debug!("webhookエンドポイントのテストリクエストを行います。");
+    let mut res = bot::send(
+        bot.post("/v2/bot/channel/webhook/test")
+            .json(&serde_json::json!({})),
+    )?;
+    Ok(res.json()?)
+}